@@ -1,4 +1,5 @@
 use crate::aabb::AABB;
+use crate::hittable::next_object_id;
 use crate::{HitRecord, Hittable, Material, Point3, Ray, Vec3};
 use std::rc::Rc;
 
@@ -9,6 +10,7 @@ pub struct XYRect {
     y0: f32,
     y1: f32,
     k: f32,
+    id: u32,
 }
 
 impl XYRect {
@@ -27,6 +29,7 @@ impl XYRect {
             y0,
             y1,
             k,
+            id: next_object_id(),
         }
     }
 }
@@ -52,6 +55,8 @@ impl Hittable for XYRect {
         let outward_normal = Vec3::new(None, None, Some(1.0));
         hit_record.set_face_normal(ray, &outward_normal);
         hit_record.material_ptr = self.material_ptr.clone();
+        hit_record.object = self.name();
+        hit_record.object_id = self.id;
         hit_record.point = ray.at(t);
 
         true
@@ -74,6 +79,7 @@ pub struct XZRect {
     z0: f32,
     z1: f32,
     k: f32,
+    id: u32,
 }
 
 impl XZRect {
@@ -92,6 +98,7 @@ impl XZRect {
             z0,
             z1,
             k,
+            id: next_object_id(),
         }
     }
 }
@@ -117,6 +124,8 @@ impl Hittable for XZRect {
         let outward_normal = Vec3::new(None, Some(1.0), None);
         hit_record.set_face_normal(ray, &outward_normal);
         hit_record.material_ptr = self.material_ptr.clone();
+        hit_record.object = self.name();
+        hit_record.object_id = self.id;
         hit_record.point = ray.at(t);
 
         true
@@ -139,6 +148,7 @@ pub struct YZRect {
     z0: f32,
     z1: f32,
     k: f32,
+    id: u32,
 }
 
 impl YZRect {
@@ -157,6 +167,7 @@ impl YZRect {
             z0,
             z1,
             k,
+            id: next_object_id(),
         }
     }
 }
@@ -182,6 +193,8 @@ impl Hittable for YZRect {
         let outward_normal = Vec3::new(Some(1.0), None, None);
         hit_record.set_face_normal(ray, &outward_normal);
         hit_record.material_ptr = self.material_ptr.clone();
+        hit_record.object = self.name();
+        hit_record.object_id = self.id;
         hit_record.point = ray.at(t);
 
         true
@@ -0,0 +1,122 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+
+// Running mean and variance of a pixel's sample luminance (Welford's algorithm), used to decide
+// when a pixel has enough samples
+#[derive(Clone, Copy, Default)]
+pub struct PixelStats {
+    pub count: i32,
+    pub mean: f32,
+    pub m2: f32,
+}
+
+impl PixelStats {
+    pub fn add(&mut self, luminance: f32) {
+        self.count += 1;
+        let delta = luminance - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (luminance - self.mean);
+    }
+
+    pub fn count(&self) -> i32 {
+        self.count
+    }
+
+    // Standard deviation of the samples
+    pub fn deviation(&self) -> f32 {
+        if self.count < 2 {
+            return 0.0;
+        }
+
+        (self.m2 / (self.count - 1) as f32).sqrt()
+    }
+
+    // Standard error of the mean, i.e. how far off the pixel probably still is
+    pub fn error(&self) -> f32 {
+        if self.count < 2 {
+            return f32::INFINITY;
+        }
+
+        (self.m2 / (self.count - 1) as f32 / self.count as f32).sqrt()
+    }
+
+    // The error measured after the output gamma, which is what the eye sees. The same absolute
+    // error is far more visible in a dark pixel than in a bright one. 'threshold' is a fraction of
+    // full white, e.g. 0.01. Samples that all came out the same (say, every one of them missed
+    // the light) say nothing about the variance yet, so such a pixel only counts as converged
+    // once it has a quarter of 'max_samples'
+    pub fn converged(&self, threshold: f32, max_samples: i32) -> bool {
+        if self.m2 <= 0.0 && self.count < max_samples / 4 {
+            return false;
+        }
+
+        let mean = self.mean.max(0.0);
+
+        (mean + self.error()).sqrt() - mean.sqrt() < threshold
+    }
+}
+
+// Writes how many samples every pixel took as a PPM image, going from black (fewest) through
+// blue and red to yellow (most). 'sample_counts' goes row by row starting at the top
+pub fn write_heatmap(
+    filename: &str,
+    sample_counts: &[i32],
+    width: i32,
+    height: i32,
+    max_samples: i32,
+) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(filename)?);
+    writeln!(out, "P3\n{} {}\n255", width, height)?;
+
+    for &count in sample_counts {
+        let x = count as f32 / max_samples.max(1) as f32;
+        let r = (3.0 * x - 1.0).clamp(0.0, 1.0);
+        let g = (3.0 * x - 2.0).clamp(0.0, 1.0);
+        let b = if x < 1.0 / 3.0 {
+            3.0 * x
+        } else {
+            (2.0 - 3.0 * x).clamp(0.0, 1.0)
+        };
+
+        writeln!(
+            out,
+            "{} {} {}",
+            (255.0 * r) as i32,
+            (255.0 * g) as i32,
+            (255.0 * b) as i32
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(samples: &[f32]) -> PixelStats {
+        let mut stats = PixelStats::default();
+        for &sample in samples {
+            stats.add(sample);
+        }
+
+        stats
+    }
+
+    #[test]
+    fn constant_samples_are_not_converged_too_early() {
+        assert!(!stats(&[0.0; 16]).converged(0.02, 256));
+        assert!(!stats(&[0.5; 16]).converged(0.02, 256));
+        assert!(stats(&[0.0; 64]).converged(0.02, 256));
+    }
+
+    #[test]
+    fn converges_once_the_error_is_small() {
+        let noisy: Vec<f32> = (0..16).map(|i| (i % 2) as f32).collect();
+        let smooth: Vec<f32> = (0..16).map(|i| 0.5 + 0.001 * (i % 2) as f32).collect();
+
+        assert!(!stats(&noisy).converged(0.02, 256));
+        assert!(stats(&smooth).converged(0.02, 256));
+    }
+}
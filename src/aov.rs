@@ -0,0 +1,139 @@
+use crate::hittable::HitRecord;
+use crate::ray::Ray;
+use crate::{Color, Point3, Vec3};
+
+// Arbitrary output variables: what the camera ray of a sample hit first. Written next to the
+// beauty pass for denoising, compositing and debugging
+#[derive(Clone, Copy)]
+pub struct AovSample {
+    pub albedo: Color,
+    // Shading normal, facing the camera
+    pub normal: Vec3,
+    pub position: Point3,
+    // Distance from the camera along the ray
+    pub depth: f32,
+    pub uv: (f32, f32),
+    // 0 when nothing was hit
+    pub object_id: u32,
+    pub material_id: u32,
+    pub hit: bool,
+}
+
+impl AovSample {
+    // The background counts as the albedo of the sky, everything else is empty
+    pub fn miss(background: &Color) -> AovSample {
+        AovSample {
+            albedo: *background,
+            normal: Vec3::new(None, None, None),
+            position: Point3::new(None, None, None),
+            depth: 0.0,
+            uv: (0.0, 0.0),
+            object_id: 0,
+            material_id: 0,
+            hit: false,
+        }
+    }
+
+    pub fn hit(ray: &Ray, hit_rec: &HitRecord) -> AovSample {
+        let material = hit_rec.material_ptr.as_ref().unwrap();
+
+        AovSample {
+            albedo: material.albedo(hit_rec),
+            normal: hit_rec.normal,
+            position: hit_rec.point,
+            // Camera rays aren't always normalized
+            depth: hit_rec.t * ray.direction().len(),
+            uv: (hit_rec.u, hit_rec.v),
+            object_id: hit_rec.object_id,
+            material_id: material.id(),
+            hit: true,
+        }
+    }
+}
+
+// Sums of the AOV samples that landed in a pixel. Albedo and normal are averaged over all samples
+// so edges blend like in the beauty pass. Depth, position and UVs only over the samples that hit
+// something, blending with the background would give meaningless values. IDs can't be averaged,
+// the first sample that hit something decides
+#[derive(Clone, Copy)]
+pub struct AovPixel {
+    pub albedo: Color,
+    pub normal: Vec3,
+    pub position: Point3,
+    pub depth: f32,
+    pub uv: (f32, f32),
+    pub object_id: u32,
+    pub material_id: u32,
+    pub samples: i32,
+    pub hits: i32,
+}
+
+impl Default for AovPixel {
+    fn default() -> AovPixel {
+        AovPixel {
+            albedo: Color::new(None, None, None),
+            normal: Vec3::new(None, None, None),
+            position: Point3::new(None, None, None),
+            depth: 0.0,
+            uv: (0.0, 0.0),
+            object_id: 0,
+            material_id: 0,
+            samples: 0,
+            hits: 0,
+        }
+    }
+}
+
+impl AovPixel {
+    pub fn add(&mut self, sample: &AovSample) {
+        self.samples += 1;
+        self.albedo += sample.albedo;
+        self.normal += sample.normal;
+
+        if sample.hit {
+            if self.hits == 0 {
+                self.object_id = sample.object_id;
+                self.material_id = sample.material_id;
+            }
+            self.hits += 1;
+            self.position += sample.position;
+            self.depth += sample.depth;
+            self.uv.0 += sample.uv.0;
+            self.uv.1 += sample.uv.1;
+        }
+    }
+
+    pub fn average(&self) -> AovSample {
+        let samples = self.samples.max(1) as f32;
+        let hits = self.hits.max(1) as f32;
+
+        AovSample {
+            albedo: self.albedo / samples,
+            normal: self.normal / samples,
+            position: self.position / hits,
+            depth: self.depth / hits,
+            uv: (self.uv.0 / hits, self.uv.1 / hits),
+            object_id: self.object_id,
+            material_id: self.material_id,
+            hit: self.hits > 0,
+        }
+    }
+}
+
+// A color that's easy to tell apart from its neighbours for every ID, black for 0
+pub fn id_color(id: u32) -> Color {
+    if id == 0 {
+        return Color::new(None, None, None);
+    }
+
+    let mut h = id.wrapping_mul(0x9e37_79b9);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+
+    Color::new(
+        Some(0.2 + 0.8 * (h & 0xff) as f32 / 255.0),
+        Some(0.2 + 0.8 * ((h >> 8) & 0xff) as f32 / 255.0),
+        Some(0.2 + 0.8 * ((h >> 16) & 0xff) as f32 / 255.0),
+    )
+}
@@ -0,0 +1,148 @@
+use crate::rtweekend::{clamp, degrees_to_radians, PI};
+use crate::sampler::Sampler;
+use crate::{Color, Vec3};
+use stb_image::image::LoadResult;
+
+// Shape of the lens opening, which is what gives out of focus highlights (bokeh) their shape.
+// Samples are points on the z = 0 plane inside the [-1,1] square (usually the unit disk), and
+// they get scaled by the camera's lens radius
+pub trait Aperture {
+    fn sample(&self, sampler: &mut dyn Sampler) -> Vec3;
+}
+
+// Perfectly round opening, same as a wide open lens
+pub struct CircularAperture;
+
+impl Aperture for CircularAperture {
+    fn sample(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        Vec3::sample_in_unit_disk(sampler.get_2d())
+    }
+}
+
+// Opening formed by 'blades' straight diaphragm blades, i.e. a regular polygon inscribed in the
+// unit circle. 'rotation' is in degrees and turns the whole polygon
+pub struct PolygonalAperture {
+    blades: i32,
+    rotation: f32,
+}
+
+impl PolygonalAperture {
+    pub fn new(blades: i32, rotation: Option<f32>) -> PolygonalAperture {
+        PolygonalAperture {
+            blades: blades.max(3),
+            rotation: degrees_to_radians(rotation.unwrap_or(0.0)),
+        }
+    }
+
+    fn vertex(&self, i: i32) -> Vec3 {
+        let angle = self.rotation + 2.0 * PI * i as f32 / self.blades as f32;
+
+        Vec3::new(Some(angle.cos()), Some(angle.sin()), None)
+    }
+}
+
+impl Aperture for PolygonalAperture {
+    fn sample(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        // Every blade contributes a triangle of the same area with the center, so pick one at
+        // random and then a uniform point inside it. What's left of the first number after
+        // picking the blade is still uniform, so it's reused for the triangle
+        let (u1, u2) = sampler.get_2d();
+        let scaled = u1 * self.blades as f32;
+        let blade = (scaled as i32).min(self.blades - 1);
+        let a = self.vertex(blade);
+        let b = self.vertex(blade + 1);
+        let mut r1 = scaled - blade as f32;
+        let mut r2 = u2;
+
+        if r1 + r2 > 1.0 {
+            r1 = 1.0 - r1;
+            r2 = 1.0 - r2;
+        }
+
+        a * r1 + b * r2
+    }
+}
+
+// Arbitrary opening read from an image. Brighter pixels let more light through, so a black
+// image with a white shape works as a mask and gray levels act as a partially transparent
+// aperture. The image is stretched over the [-1,1] square
+pub struct ImageAperture {
+    cdf: Vec<f32>,
+    width: usize,
+    height: usize,
+}
+
+impl ImageAperture {
+    pub fn new(filename: &str) -> ImageAperture {
+        let image = match stb_image::image::load(filename) {
+            LoadResult::ImageU8(image) => image,
+            _ => {
+                eprintln!("Could not load aperture mask {}", filename);
+                return ImageAperture {
+                    cdf: vec![],
+                    width: 0,
+                    height: 0,
+                };
+            }
+        };
+        // Running sum of the luminance of every pixel. Gray images are used as they are and the
+        // alpha channel is ignored
+        let mut cdf = Vec::with_capacity(image.width * image.height);
+        let mut total = 0.0;
+
+        for pixel in image.data.chunks(image.depth) {
+            total += if image.depth >= 3 {
+                Color::new(
+                    Some(pixel[0] as f32),
+                    Some(pixel[1] as f32),
+                    Some(pixel[2] as f32),
+                )
+                .luminance()
+            } else {
+                pixel[0] as f32
+            };
+            cdf.push(total);
+        }
+
+        if total <= 0.0 {
+            eprintln!("Aperture mask {} is completely black", filename);
+            cdf.clear();
+        }
+
+        ImageAperture {
+            cdf,
+            width: image.width,
+            height: image.height,
+        }
+    }
+}
+
+impl Aperture for ImageAperture {
+    fn sample(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        let (u1, u2) = sampler.get_2d();
+
+        // Fall back to a round aperture if there is no usable mask
+        if self.cdf.is_empty() {
+            return Vec3::sample_in_unit_disk((u1, u2));
+        }
+
+        let target = u1 * *self.cdf.last().unwrap();
+        let pixel = self
+            .cdf
+            .partition_point(|&c| c <= target)
+            .min(self.cdf.len() - 1);
+        // Where the target fell inside the pixel's slice of the CDF is uniform too, use it to
+        // jitter horizontally
+        let start = if pixel > 0 { self.cdf[pixel - 1] } else { 0.0 };
+        let jitter = clamp((target - start) / (self.cdf[pixel] - start), 0.0, 1.0);
+        let x = (pixel % self.width) as f32 + jitter;
+        let y = (pixel / self.width) as f32 + u2;
+
+        // Image rows go top to bottom, so flip y
+        Vec3::new(
+            Some(2.0 * x / self.width as f32 - 1.0),
+            Some(1.0 - 2.0 * y / self.height as f32),
+            None,
+        )
+    }
+}
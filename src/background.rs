@@ -0,0 +1,87 @@
+use crate::rtweekend::PI;
+use crate::texture::Texture;
+use crate::{Color, Vec3};
+use std::rc::Rc;
+
+// What a ray that misses everything runs into: light coming from infinitely far away, depending
+// only on the direction. It lights the scene too
+pub trait Background {
+    fn radiance(&self, direction: &Vec3) -> Color;
+
+    // Backgrounds with bright spots (like the sun in an HDR map) are worth sampling directly at
+    // every diffuse bounce instead of waiting for rays to find them. Those say so and override
+    // sample() and pdf()
+    fn importance_sampled(&self) -> bool {
+        false
+    }
+
+    // A direction and its pdf (per solid angle). Uniform over the sphere unless overridden
+    fn sample(&self, u: (f32, f32)) -> (Vec3, f32) {
+        (Vec3::sample_unit_vector(u), 1.0 / (4.0 * PI))
+    }
+
+    fn pdf(&self, _direction: &Vec3) -> f32 {
+        1.0 / (4.0 * PI)
+    }
+}
+
+// The same color everywhere
+pub struct ConstantBackground {
+    color: Color,
+}
+
+impl ConstantBackground {
+    pub fn new(color: Color) -> ConstantBackground {
+        ConstantBackground { color }
+    }
+}
+
+impl Background for ConstantBackground {
+    fn radiance(&self, _direction: &Vec3) -> Color {
+        self.color
+    }
+}
+
+// Blends from 'bottom' straight down to 'top' straight up, the sky of the first book is white to
+// (0.5, 0.7, 1.0)
+pub struct GradientBackground {
+    bottom: Color,
+    top: Color,
+}
+
+impl GradientBackground {
+    pub fn new(bottom: Color, top: Color) -> GradientBackground {
+        GradientBackground { bottom, top }
+    }
+}
+
+impl Background for GradientBackground {
+    fn radiance(&self, direction: &Vec3) -> Color {
+        let t = 0.5 * (Vec3::unit_vector(*direction).y() + 1.0);
+
+        self.bottom * (1.0 - t) + self.top * t
+    }
+}
+
+// Any texture wrapped around the scene. It gets the same UVs as on a sphere (so an
+// ImageTexture should be an equirectangular panorama) and the unit direction as the point, which
+// is what procedural textures go by
+pub struct TextureBackground {
+    texture: Rc<dyn Texture>,
+}
+
+impl TextureBackground {
+    pub fn new(texture: Rc<dyn Texture>) -> TextureBackground {
+        TextureBackground { texture }
+    }
+}
+
+impl Background for TextureBackground {
+    fn radiance(&self, direction: &Vec3) -> Color {
+        let direction = Vec3::unit_vector(*direction);
+        let theta = (-direction.y()).clamp(-1.0, 1.0).acos();
+        let phi = (-direction.z()).atan2(direction.x()) + PI;
+
+        self.texture.value(phi / (2.0 * PI), theta / PI, &direction)
+    }
+}
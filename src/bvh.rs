@@ -1,4 +1,5 @@
 use crate::aabb::AABB;
+use crate::rtweekend::Rng;
 use crate::{random, HitRecord, Hittable, HittableList, Ray, Vec3};
 use std::cmp::Ordering;
 use std::rc::Rc;
@@ -10,8 +11,8 @@ pub struct BvhNode {
 }
 
 impl BvhNode {
-    fn from(list: &HittableList, time0: f32, time1: f32) -> BvhNode {
-        return BvhNode::new(&list.objects, 0, list.objects.len(), time0, time1);
+    fn from(list: &HittableList, time0: f32, time1: f32, rng: &mut Rng) -> BvhNode {
+        return BvhNode::new(&list.objects, 0, list.objects.len(), time0, time1, rng);
     }
 
     fn new(
@@ -20,9 +21,10 @@ impl BvhNode {
         end: usize,
         time0: f32,
         time1: f32,
+        rng: &mut Rng,
     ) -> BvhNode {
         let mut objects = src_objects.clone();
-        let axis = random::<i32>(0, 3);
+        let axis = random::<i32>(rng, 0, 3);
         let comparator = if axis == 0 {
             AABB::box_x_compare
         } else if axis == 1 {
@@ -48,8 +50,8 @@ impl BvhNode {
         } else {
             objects.sort_by(|a, b| comparator(a.clone(), b.clone()));
             let mid = start + object_span / 2;
-            temp_left = Rc::new(Self::new(&objects, start, mid, time0, time1));
-            temp_right = Rc::new(Self::new(&objects, mid, end, time0, time1))
+            temp_left = Rc::new(Self::new(&objects, start, mid, time0, time1, rng));
+            temp_right = Rc::new(Self::new(&objects, mid, end, time0, time1, rng))
         }
 
         let mut box_left = AABB::new(Vec3::new(None, None, None), Vec3::new(None, None, None));
@@ -1,7 +1,193 @@
-use crate::rtweekend::degrees_to_radians;
-use crate::{random, Point3, Ray, Vec3};
+use crate::aperture::{Aperture, CircularAperture};
+use crate::lens::{ExitPupil, LensSystem};
+use crate::rtweekend::{clamp, degrees_to_radians, INFINITY, PI};
+use crate::sampler::Sampler;
+use crate::{HitRecord, Hittable, Point3, Ray, Vec3};
+use std::rc::Rc;
 
-pub struct Camera {
+// Anything that can turn normalized image coordinates into a primary ray. 's' goes left to right
+// and 't' bottom to top, both in [0,1]. Projections that don't cover the whole frame (like a
+// circular fisheye) return None for the parts of the image they can't see, and lenses return
+// None for rays blocked inside the lens. Either way the sample counts as black. Along with the ray
+// comes the weight of the light it brings back, 1 unless the camera darkens some rays (like a
+// real lens vignetting towards the corners)
+pub trait Camera {
+    fn get_ray(&self, s: f32, t: f32, sampler: &mut dyn Sampler) -> Option<(Ray, f32)>;
+
+    // Scale applied to the radiance gathered through this camera before it is written out
+    fn exposure(&self) -> f32 {
+        1.0
+    }
+
+    // Interval [time0, time1] the shutter is open for, the rays' times are spread over it
+    fn shutter(&self) -> (f32, f32);
+}
+
+// Real world camera settings. Sensor size and focal length are in millimeters, shutter speed is
+// in seconds and 'units_per_meter' says how big a meter is in scene units, which is needed to turn
+// the physical aperture into a lens radius (e.g. 1000.0 for a scene modelled in millimeters)
+pub struct PhysicalSettings {
+    pub sensor_width: f32,
+    pub sensor_height: f32,
+    pub focal_length: f32,
+    pub f_number: f32,
+    pub shutter_speed: f32,
+    pub iso: f32,
+    pub units_per_meter: f32,
+}
+
+impl PhysicalSettings {
+    // 36x24mm sensor with the scene modelled in meters
+    pub fn full_frame(
+        focal_length: f32,
+        f_number: f32,
+        shutter_speed: f32,
+        iso: f32,
+    ) -> PhysicalSettings {
+        PhysicalSettings {
+            sensor_width: 36.0,
+            sensor_height: 24.0,
+            focal_length,
+            f_number,
+            shutter_speed,
+            iso,
+            units_per_meter: 1.0,
+        }
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        self.sensor_width / self.sensor_height
+    }
+
+    pub fn vertical_fov(&self) -> f32 {
+        2.0 * f32::atan(self.sensor_height / (2.0 * self.focal_length)) * 180.0 / PI
+    }
+
+    // Diameter of the entrance pupil in scene units
+    pub fn aperture(&self) -> f32 {
+        self.focal_length / self.f_number / 1000.0 * self.units_per_meter
+    }
+
+    // Exposure value at ISO 100 for these settings, the usual EV photographers talk about
+    pub fn ev100(&self) -> f32 {
+        f32::log2(self.f_number * self.f_number / self.shutter_speed * 100.0 / self.iso)
+    }
+
+    // Standard saturation based exposure (a 1.2 factor leaves room for highlights). Emitted
+    // values are treated as luminance in cd/m^2, so a sunny day setup (f/16, 1/100s, ISO 100)
+    // maps a ~30000 nit surface to 1.0
+    pub fn exposure(&self) -> f32 {
+        1.0 / (1.2 * f32::powf(2.0, self.ev100()))
+    }
+}
+
+// Uniformly distributed moment between the shutter opening and closing
+fn random_time(sampler: &mut dyn Sampler, time0: f32, time1: f32) -> f32 {
+    time0 + (time1 - time0) * sampler.get_1d()
+}
+
+// Builds the orthonormal basis every camera uses. 'w' points from the target back towards the
+// camera, 'u' to the right and 'v' up
+fn camera_basis(look_from: Point3, look_at: Point3, v_up: Vec3) -> (Vec3, Vec3, Vec3) {
+    let w = Vec3::unit_vector(look_from - look_at);
+    let u = Vec3::unit_vector(v_up.cross(&w));
+    let v = w.cross(&u);
+
+    (u, v, w)
+}
+
+// Where a thin lens camera puts its plane of focus
+pub enum Focus {
+    // Fixed distance from the camera, measured along the view direction
+    Distance(f32),
+    // The point the camera is aimed at
+    LookAt,
+    // Any point in the world
+    Point(Point3),
+    // Whatever the ray through the center of the image hits first
+    Auto,
+}
+
+// Distance from 'origin' along 'view_dir' (a unit vector) to what 'focus' points at, for cameras
+// looking at 'look_at'. Auto focus follows 'center', the direction through the center of the
+// image. None (after saying why) when there's nothing usable to focus on
+pub fn focus_distance(
+    focus: &Focus,
+    origin: Point3,
+    look_at: Point3,
+    view_dir: Vec3,
+    center: Vec3,
+    world: &dyn Hittable,
+    time: f32,
+) -> Option<f32> {
+    let focus_dist = match focus {
+        Focus::Distance(dist) => *dist,
+        Focus::LookAt => (look_at - origin).dot(&view_dir),
+        Focus::Point(point) => (*point - origin).dot(&view_dir),
+        Focus::Auto => {
+            let ray = Ray::new(Some(origin), Some(center), Some(time));
+            let mut hit_rec = HitRecord::empty();
+
+            if !world.hit(&ray, 0.001, INFINITY, &mut hit_rec) {
+                eprintln!("Auto focus: the center of the image doesn't hit anything");
+                return None;
+            }
+
+            (hit_rec.point - origin).dot(&view_dir)
+        }
+    };
+
+    if focus_dist <= 0.0 {
+        eprintln!("Focus point is behind the camera, ignoring it");
+        return None;
+    }
+
+    Some(focus_dist)
+}
+
+// Order in which a rolling shutter reads the sensor out
+pub enum ReadoutDirection {
+    TopToBottom,
+    BottomToTop,
+    LeftToRight,
+    RightToLeft,
+}
+
+// CMOS style rolling shutter. Instead of the whole frame being exposed during [time0, time1],
+// lines are read out one after another over 'readout_time'. Every line is exposed for
+// (time1 - time0 - readout_time), the first one starting at time0 and the last one finishing at
+// time1. A readout time of 0 is a global shutter, and one as long as the whole interval exposes
+// every line for an instant
+pub struct RollingShutter {
+    pub direction: ReadoutDirection,
+    pub readout_time: f32,
+}
+
+impl RollingShutter {
+    // Moves 'time', spread over the whole of [time0, time1], into the part of it the line through
+    // (s, t) is exposed for
+    fn line_time(&self, s: f32, t: f32, time: f32, time0: f32, time1: f32) -> f32 {
+        // How far along the readout this pixel's line is, from 0 to 1
+        let line = match self.direction {
+            ReadoutDirection::TopToBottom => 1.0 - t,
+            ReadoutDirection::BottomToTop => t,
+            ReadoutDirection::LeftToRight => s,
+            ReadoutDirection::RightToLeft => 1.0 - s,
+        };
+        let readout_time = clamp(self.readout_time, 0.0, time1 - time0);
+        let start = time0 + line * readout_time;
+        let exposure_time = time1 - time0 - readout_time;
+        let fraction = if time1 > time0 {
+            (time - time0) / (time1 - time0)
+        } else {
+            0.0
+        };
+
+        start + exposure_time * fraction
+    }
+}
+
+pub struct PerspectiveCamera {
     origin: Point3,
     horizontal: Vec3,
     vertical: Vec3,
@@ -9,12 +195,20 @@ pub struct Camera {
     u: Vec3,
     v: Vec3,
     w: Vec3,
+    look_at: Point3,
+    focus_dist: f32,
+    focus_normal: Vec3,
+    shift_x: f32,
+    shift_y: f32,
     lens_radius: f32,
+    aperture_shape: Rc<dyn Aperture>,
+    cat_eye: f32,
+    exposure: f32,
     time0: f32,
     time1: f32,
 }
 
-impl Camera {
+impl PerspectiveCamera {
     pub fn new(
         look_from: Point3,
         look_at: Point3,
@@ -25,22 +219,20 @@ impl Camera {
         focus_dist: f32,
         _time0: Option<f32>,
         _time1: Option<f32>,
-    ) -> Camera {
+    ) -> PerspectiveCamera {
         let theta = degrees_to_radians(vert_fov);
         let h = (theta / 2.0).tan();
         let viewport_height = 2.0 * h;
         let viewport_width = aspect_ratio * viewport_height;
 
-        let w = Vec3::unit_vector(look_from - look_at);
-        let u = Vec3::unit_vector(v_up.cross(&w));
-        let v = w.cross(&u);
+        let (u, v, w) = camera_basis(look_from, look_at, v_up);
 
         let origin = look_from;
         let horizontal = u * viewport_width * focus_dist;
         let vertical = v * viewport_height * focus_dist;
         let llc = origin - horizontal / 2.0 - vertical / 2.0 - w * focus_dist;
 
-        Camera {
+        PerspectiveCamera {
             origin,
             horizontal,
             vertical,
@@ -48,24 +240,638 @@ impl Camera {
             u,
             v,
             w,
+            look_at,
+            focus_dist,
+            focus_normal: w,
+            shift_x: 0.0,
+            shift_y: 0.0,
             lens_radius: aperture / 2.0,
+            aperture_shape: Rc::new(CircularAperture),
+            cat_eye: 0.0,
+            exposure: 1.0,
             time0: _time0.unwrap_or(0.0),
             time1: _time1.unwrap_or(0.0),
         }
     }
 
-    pub fn get_ray(&self, s: f32, t: f32) -> Ray {
-        let rd = Vec3::random_in_unit_disk() * self.lens_radius;
+    // Thin lens camera driven by real camera settings instead of raw numbers. The field of view
+    // comes from the sensor height and focal length, the lens radius from the f-number and the
+    // exposure from f-number, shutter speed and ISO. The image should be rendered at
+    // 'settings.aspect_ratio()'
+    pub fn physical(
+        look_from: Point3,
+        look_at: Point3,
+        v_up: Vec3,
+        settings: &PhysicalSettings,
+        focus_dist: f32,
+        _time0: Option<f32>,
+        _time1: Option<f32>,
+    ) -> PerspectiveCamera {
+        let mut camera = PerspectiveCamera::new(
+            look_from,
+            look_at,
+            v_up,
+            settings.vertical_fov(),
+            settings.aspect_ratio(),
+            settings.aperture(),
+            focus_dist,
+            _time0,
+            _time1,
+        );
+        camera.exposure = settings.exposure();
+
+        camera
+    }
+
+    // Moves the plane of focus. 'world' is only traced for Focus::Auto, and if the center ray
+    // misses everything (or the point is behind the camera) the focus distance stays as it was
+    pub fn set_focus(&mut self, focus: &Focus, world: &dyn Hittable) {
+        let center = self.lower_left_corner + self.horizontal * 0.5 + self.vertical * 0.5;
+
+        if let Some(focus_dist) = focus_distance(
+            focus,
+            self.origin,
+            self.look_at,
+            -self.w,
+            center - self.origin,
+            world,
+            self.time0,
+        ) {
+            self.set_focus_dist(focus_dist);
+        }
+    }
+
+    // The viewport lives on the plane of focus, so it has to be scaled and moved along with it
+    fn set_focus_dist(&mut self, focus_dist: f32) {
+        let scale = focus_dist / self.focus_dist;
+        self.horizontal *= scale;
+        self.vertical *= scale;
+        self.focus_dist = focus_dist;
+        self.update_lower_left_corner();
+    }
+
+    fn update_lower_left_corner(&mut self) {
+        self.lower_left_corner = self.origin
+            - self.horizontal * (0.5 - self.shift_x)
+            - self.vertical * (0.5 - self.shift_y)
+            - self.w * self.focus_dist;
+    }
+
+    // Slides the image plane sideways without turning the camera, as a fraction of the frame
+    // width and height. Keeping the camera level and shifting up is how architectural shots
+    // frame tall buildings while keeping verticals parallel
+    pub fn set_lens_shift(&mut self, shift_x: f32, shift_y: f32) {
+        self.shift_x = shift_x;
+        self.shift_y = shift_y;
+        self.update_lower_left_corner();
+    }
+
+    // Tilts the plane of focus (Scheimpflug principle) instead of keeping it parallel to the
+    // image plane. Both angles are in degrees and pivot around the point where the view axis
+    // crosses the plane of focus. A positive 'tilt' pushes the top of the frame further away, like
+    // a plane of focus lying along the ground, and a positive 'swing' does the same for the right
+    pub fn set_tilt(&mut self, tilt: f32, swing: f32) {
+        self.focus_normal = Vec3::unit_vector(
+            self.w
+                + self.v * degrees_to_radians(tilt).tan()
+                + self.u * degrees_to_radians(swing).tan(),
+        );
+    }
+
+    // Replaces the default round aperture, see aperture.rs for the available shapes
+    pub fn set_aperture_shape(&mut self, aperture_shape: Rc<dyn Aperture>) {
+        self.aperture_shape = aperture_shape;
+    }
+
+    // Optical vignetting. Towards the edges of the frame the lens barrel clips the aperture, which
+    // turns bokeh into 'cat eyes' and darkens the corners. 'strength' is how far the clipping
+    // circle moves at the corners of the image, in aperture radii. 0 disables it
+    pub fn set_cat_eye(&mut self, strength: f32) {
+        self.cat_eye = strength;
+    }
+}
+
+impl Camera for PerspectiveCamera {
+    fn get_ray(&self, s: f32, t: f32, sampler: &mut dyn Sampler) -> Option<(Ray, f32)> {
+        let lens_point = self.aperture_shape.sample(sampler);
+
+        // A pinhole has nothing for the barrel to clip
+        if self.cat_eye > 0.0 && self.lens_radius > 0.0 {
+            // Position of the pixel relative to the center, 1.0 at the corners
+            let aspect_ratio = self.horizontal.len() / self.vertical.len();
+            let pixel = Vec3::new(
+                Some((2.0 * s - 1.0) * aspect_ratio),
+                Some(2.0 * t - 1.0),
+                None,
+            ) / (aspect_ratio * aspect_ratio + 1.0).sqrt();
+
+            if (lens_point - pixel * self.cat_eye).len_squared() > 1.0 {
+                return None;
+            }
+        }
+
+        let rd = lens_point * self.lens_radius;
         let offset = self.u * rd.x() + self.v * rd.y();
+        // Every ray leaving the lens for this pixel has to converge where the ray through the
+        // center of the lens meets the plane of focus. Without tilt that's simply the point on
+        // the viewport
+        let direction =
+            self.lower_left_corner + self.horizontal * s + self.vertical * t - self.origin;
+        let plane_point = self.origin - self.w * self.focus_dist;
+        let denominator = direction.dot(&self.focus_normal);
+        let focus_t = (plane_point - self.origin).dot(&self.focus_normal) / denominator;
+        // Pixels whose center ray never reaches the tilted plane are focused at infinity
+        let focus_point = if denominator.abs() > 1e-8 && focus_t > 0.0 {
+            self.origin + direction * focus_t
+        } else {
+            self.origin + offset + direction
+        };
+
+        Some((
+            Ray::new(
+                Some(self.origin + offset),
+                Some(focus_point - self.origin - offset),
+                Some(random_time(sampler, self.time0, self.time1)),
+            ),
+            1.0,
+        ))
+    }
+
+    fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    fn shutter(&self) -> (f32, f32) {
+        (self.time0, self.time1)
+    }
+}
+
+// Parallel projection, every ray points straight down -w and only the origin moves across the
+// image plane. 'viewport_height' is the height of the visible area in world units
+pub struct OrthographicCamera {
+    horizontal: Vec3,
+    vertical: Vec3,
+    lower_left_corner: Point3,
+    direction: Vec3,
+    time0: f32,
+    time1: f32,
+}
+
+impl OrthographicCamera {
+    pub fn new(
+        look_from: Point3,
+        look_at: Point3,
+        v_up: Vec3,
+        viewport_height: f32,
+        aspect_ratio: f32,
+        _time0: Option<f32>,
+        _time1: Option<f32>,
+    ) -> OrthographicCamera {
+        let (u, v, w) = camera_basis(look_from, look_at, v_up);
+        let horizontal = u * viewport_height * aspect_ratio;
+        let vertical = v * viewport_height;
+
+        OrthographicCamera {
+            horizontal,
+            vertical,
+            lower_left_corner: look_from - horizontal / 2.0 - vertical / 2.0,
+            direction: -w,
+            time0: _time0.unwrap_or(0.0),
+            time1: _time1.unwrap_or(0.0),
+        }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn get_ray(&self, s: f32, t: f32, sampler: &mut dyn Sampler) -> Option<(Ray, f32)> {
+        Some((
+            Ray::new(
+                Some(self.lower_left_corner + self.horizontal * s + self.vertical * t),
+                Some(self.direction),
+                Some(random_time(sampler, self.time0, self.time1)),
+            ),
+            1.0,
+        ))
+    }
+
+    fn shutter(&self) -> (f32, f32) {
+        (self.time0, self.time1)
+    }
+}
+
+// Equidistant ('f-theta') fisheye. The image circle touches the top and bottom of the frame and
+// its diameter covers 'fov' degrees, so 180 gives a full hemisphere. Anything outside the circle
+// returns no ray
+pub struct FisheyeCamera {
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    half_fov: f32,
+    aspect_ratio: f32,
+    time0: f32,
+    time1: f32,
+}
+
+impl FisheyeCamera {
+    pub fn new(
+        look_from: Point3,
+        look_at: Point3,
+        v_up: Vec3,
+        fov: f32,
+        aspect_ratio: f32,
+        _time0: Option<f32>,
+        _time1: Option<f32>,
+    ) -> FisheyeCamera {
+        let (u, v, w) = camera_basis(look_from, look_at, v_up);
+
+        FisheyeCamera {
+            origin: look_from,
+            u,
+            v,
+            w,
+            half_fov: degrees_to_radians(fov) / 2.0,
+            aspect_ratio,
+            time0: _time0.unwrap_or(0.0),
+            time1: _time1.unwrap_or(0.0),
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn get_ray(&self, s: f32, t: f32, sampler: &mut dyn Sampler) -> Option<(Ray, f32)> {
+        let x = (2.0 * s - 1.0) * self.aspect_ratio;
+        let y = 2.0 * t - 1.0;
+        let r = (x * x + y * y).sqrt();
+
+        if r > 1.0 {
+            return None;
+        }
+
+        // Distance from the center of the image is proportional to the angle from the view axis
+        let theta = r * self.half_fov;
+        let phi = f32::atan2(y, x);
+        let direction = self.u * (theta.sin() * phi.cos()) + self.v * (theta.sin() * phi.sin())
+            - self.w * theta.cos();
+
+        Some((
+            Ray::new(
+                Some(self.origin),
+                Some(direction),
+                Some(random_time(sampler, self.time0, self.time1)),
+            ),
+            1.0,
+        ))
+    }
+
+    fn shutter(&self) -> (f32, f32) {
+        (self.time0, self.time1)
+    }
+}
+
+// Full 360x180 degree latitude-longitude panorama, meant to be rendered at a 2:1 aspect ratio.
+// The center of the image looks at 'look_at', 's' sweeps the longitude and 't' the latitude, so
+// the output can be used directly as an environment map
+pub struct EquirectangularCamera {
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    time0: f32,
+    time1: f32,
+}
+
+impl EquirectangularCamera {
+    pub fn new(
+        look_from: Point3,
+        look_at: Point3,
+        v_up: Vec3,
+        _time0: Option<f32>,
+        _time1: Option<f32>,
+    ) -> EquirectangularCamera {
+        let (u, v, w) = camera_basis(look_from, look_at, v_up);
+
+        EquirectangularCamera {
+            origin: look_from,
+            u,
+            v,
+            w,
+            time0: _time0.unwrap_or(0.0),
+            time1: _time1.unwrap_or(0.0),
+        }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn get_ray(&self, s: f32, t: f32, sampler: &mut dyn Sampler) -> Option<(Ray, f32)> {
+        let phi = (s - 0.5) * 2.0 * PI;
+        let latitude = (t - 0.5) * PI;
+        let direction = self.u * (latitude.cos() * phi.sin()) + self.v * latitude.sin()
+            - self.w * (latitude.cos() * phi.cos());
+
+        Some((
+            Ray::new(
+                Some(self.origin),
+                Some(direction),
+                Some(random_time(sampler, self.time0, self.time1)),
+            ),
+            1.0,
+        ))
+    }
+
+    fn shutter(&self) -> (f32, f32) {
+        (self.time0, self.time1)
+    }
+}
+
+// Omnidirectional stereo (ODS) for VR headsets. Both eyes are equirectangular panoramas stacked
+// top/bottom, left eye on top. Instead of two fixed eye positions, every column gets its own
+// pair of eyes on a circle of diameter 'ipd' around the camera, looking tangentially, which is
+// what keeps the stereo right in every viewing direction. Towards the poles that breaks down, so
+// the eye separation fades out between the pole merge angle and straight up/down
+pub struct OdsCamera {
+    origin: Point3,
+    look_at: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    ipd: f32,
+    pole_merge: f32,
+    lens_radius: f32,
+    focus_dist: f32,
+    time0: f32,
+    time1: f32,
+}
+
+impl OdsCamera {
+    pub fn new(
+        look_from: Point3,
+        look_at: Point3,
+        v_up: Vec3,
+        ipd: f32,
+        aperture: f32,
+        _time0: Option<f32>,
+        _time1: Option<f32>,
+    ) -> OdsCamera {
+        let (u, v, w) = camera_basis(look_from, look_at, v_up);
+
+        OdsCamera {
+            origin: look_from,
+            look_at,
+            u,
+            v,
+            w,
+            ipd,
+            pole_merge: degrees_to_radians(60.0),
+            lens_radius: aperture / 2.0,
+            focus_dist: (look_at - look_from).len(),
+            time0: _time0.unwrap_or(0.0),
+            time1: _time1.unwrap_or(0.0),
+        }
+    }
 
-        Ray::new(
-            Some(self.origin + offset),
-            Some(
-                self.lower_left_corner + self.horizontal * s + self.vertical * t
-                    - self.origin
-                    - offset,
+    // Focused on the point it's aimed at until this is called. The focus distance is measured
+    // straight ahead and used in every direction
+    pub fn set_focus(&mut self, focus: &Focus, world: &dyn Hittable) {
+        let ahead = -self.w;
+
+        if let Some(focus_dist) = focus_distance(
+            focus,
+            self.origin,
+            self.look_at,
+            ahead,
+            ahead,
+            world,
+            self.time0,
+        ) {
+            self.focus_dist = focus_dist;
+        }
+    }
+
+    // Latitude, in degrees, where the eyes start merging. 90 disables merging
+    pub fn set_pole_merge(&mut self, angle: f32) {
+        self.pole_merge = degrees_to_radians(clamp(angle, 0.0, 90.0));
+    }
+}
+
+impl Camera for OdsCamera {
+    fn get_ray(&self, s: f32, t: f32, sampler: &mut dyn Sampler) -> Option<(Ray, f32)> {
+        // Top half of the image is the left eye
+        let (eye_t, eye_side) = if t >= 0.5 {
+            (2.0 * t - 1.0, -1.0)
+        } else {
+            (2.0 * t, 1.0)
+        };
+        let phi = (s - 0.5) * 2.0 * PI;
+        let latitude = (eye_t - 0.5) * PI;
+        let direction = self.u * (latitude.cos() * phi.sin()) + self.v * latitude.sin()
+            - self.w * (latitude.cos() * phi.cos());
+        // Horizontal 'right' for this column, the eyes sit along it
+        let right = self.u * phi.cos() + self.w * phi.sin();
+        let up = right.cross(&direction);
+
+        let mut separation = 1.0;
+        if latitude.abs() > self.pole_merge {
+            let x = (PI / 2.0 - latitude.abs()) / (PI / 2.0 - self.pole_merge);
+            separation = x * x * (3.0 - 2.0 * x);
+        }
+        let eye = self.origin + right * (eye_side * separation * self.ipd / 2.0);
+
+        // Thin lens around each eye, perpendicular to the viewing direction
+        let rd = Vec3::sample_in_unit_disk(sampler.get_2d()) * self.lens_radius;
+        let offset = right * rd.x() + up * rd.y();
+        let focus_point = eye + direction * self.focus_dist;
+
+        Some((
+            Ray::new(
+                Some(eye + offset),
+                Some(focus_point - eye - offset),
+                Some(random_time(sampler, self.time0, self.time1)),
+            ),
+            1.0,
+        ))
+    }
+
+    fn shutter(&self) -> (f32, f32) {
+        (self.time0, self.time1)
+    }
+}
+
+// Traces camera rays through a real lens prescription instead of an ideal thin lens, which
+// brings along the lens' own distortion, vignetting and bokeh. Sensor size, shutter, ISO and scene
+// units come from 'settings'. The focal length is whatever the prescription gives, and the
+// f-number stops the lens down from its maximum aperture
+pub struct RealisticCamera {
+    origin: Point3,
+    look_at: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    lens: LensSystem,
+    exit_pupil: ExitPupil,
+    // Pupil bounds area at the center of the film, what ray weights are relative to
+    center_area: f32,
+    film_width: f32,
+    film_height: f32,
+    scale: f32,
+    exposure: f32,
+    time0: f32,
+    time1: f32,
+}
+
+impl RealisticCamera {
+    pub fn new(
+        look_from: Point3,
+        look_at: Point3,
+        v_up: Vec3,
+        mut lens: LensSystem,
+        settings: &PhysicalSettings,
+        _time0: Option<f32>,
+        _time1: Option<f32>,
+    ) -> RealisticCamera {
+        let (u, v, w) = camera_basis(look_from, look_at, v_up);
+        // Millimeters to scene units
+        let scale = settings.units_per_meter / 1000.0;
+
+        lens.set_f_number(settings.f_number);
+        let (exit_pupil, center_area) = RealisticCamera::focus_lens(
+            &mut lens,
+            (look_at - look_from).len() / scale,
+            settings.sensor_width,
+            settings.sensor_height,
+        );
+
+        RealisticCamera {
+            origin: look_from,
+            look_at,
+            u,
+            v,
+            w,
+            lens,
+            exit_pupil,
+            center_area,
+            film_width: settings.sensor_width,
+            film_height: settings.sensor_height,
+            scale,
+            exposure: settings.exposure(),
+            time0: _time0.unwrap_or(0.0),
+            time1: _time1.unwrap_or(0.0),
+        }
+    }
+
+    // Focused on the point it's aimed at until this is called. Moves the lens, which changes
+    // the exit pupil, so it's worked out again
+    pub fn set_focus(&mut self, focus: &Focus, world: &dyn Hittable) {
+        let ahead = -self.w;
+
+        if let Some(focus_dist) = focus_distance(
+            focus,
+            self.origin,
+            self.look_at,
+            ahead,
+            ahead,
+            world,
+            self.time0,
+        ) {
+            let (exit_pupil, center_area) = RealisticCamera::focus_lens(
+                &mut self.lens,
+                focus_dist / self.scale,
+                self.film_width,
+                self.film_height,
+            );
+            self.exit_pupil = exit_pupil;
+            self.center_area = center_area;
+        }
+    }
+
+    // Focuses the lens 'distance' millimeters away and bounds its exit pupil over the film. Also
+    // gives the pupil bounds area at the center of the film
+    fn focus_lens(
+        lens: &mut LensSystem,
+        distance: f32,
+        film_width: f32,
+        film_height: f32,
+    ) -> (ExitPupil, f32) {
+        lens.focus(distance);
+        let film_diagonal = (film_width * film_width + film_height * film_height).sqrt();
+        let exit_pupil = lens.exit_pupil(film_diagonal);
+        let center_area = exit_pupil.area(&Vec3::new(None, None, None));
+
+        (exit_pupil, center_area)
+    }
+}
+
+impl Camera for RealisticCamera {
+    fn get_ray(&self, s: f32, t: f32, sampler: &mut dyn Sampler) -> Option<(Ray, f32)> {
+        // The lens flips the image, so the top of the picture is at the bottom of the film
+        let film_point = Vec3::new(
+            Some(-(s - 0.5) * self.film_width),
+            Some(-(t - 0.5) * self.film_height),
+            None,
+        );
+        let rear_point = self.exit_pupil.sample(&film_point, sampler);
+        let film_ray = Ray::new(Some(film_point), Some(rear_point - film_point), None);
+        let lens_ray = self.lens.trace_from_film(&film_ray)?;
+        // Light reaching the film falls off with cos^4 of the angle it arrives at, and rays are
+        // aimed at pupil bounds whose size changes across the film, so each one stands for that
+        // much area of the rear element (cos^4 * area / rear_z^2, like pbrt). Relative to the
+        // center of the film, since the exposure already accounts for the f-number
+        let cos_theta = Vec3::unit_vector(film_ray.direction()).z().abs();
+        let weight = cos_theta.powi(4) * self.exit_pupil.area(&film_point) / self.center_area;
+
+        // Lens space to world space. Lens space looks down -z just like the camera looks down -w
+        let o = lens_ray.origin();
+        let d = lens_ray.direction();
+
+        Some((
+            Ray::new(
+                Some(self.origin + (self.u * o.x() + self.v * o.y() + self.w * o.z()) * self.scale),
+                Some(self.u * d.x() + self.v * d.y() + self.w * d.z()),
+                Some(random_time(sampler, self.time0, self.time1)),
             ),
-            Some(random(self.time0, self.time1)),
-        )
+            weight,
+        ))
+    }
+
+    fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    fn shutter(&self) -> (f32, f32) {
+        (self.time0, self.time1)
+    }
+}
+
+// Gives any camera a rolling shutter. The rays come from 'camera' and the readout happens over
+// its shutter interval, only the time of each ray is moved into when its line is exposed
+pub struct RollingShutterCamera {
+    camera: Rc<dyn Camera>,
+    shutter: RollingShutter,
+}
+
+impl RollingShutterCamera {
+    pub fn new(camera: Rc<dyn Camera>, shutter: RollingShutter) -> RollingShutterCamera {
+        RollingShutterCamera { camera, shutter }
+    }
+}
+
+impl Camera for RollingShutterCamera {
+    fn get_ray(&self, s: f32, t: f32, sampler: &mut dyn Sampler) -> Option<(Ray, f32)> {
+        let (ray, weight) = self.camera.get_ray(s, t, sampler)?;
+        let (time0, time1) = self.camera.shutter();
+        let time = self.shutter.line_time(s, t, ray.time(), time0, time1);
+
+        Some((
+            Ray::new(Some(ray.origin()), Some(ray.direction()), Some(time)),
+            weight,
+        ))
+    }
+
+    fn exposure(&self) -> f32 {
+        self.camera.exposure()
+    }
+
+    fn shutter(&self) -> (f32, f32) {
+        self.camera.shutter()
     }
 }
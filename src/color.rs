@@ -0,0 +1,131 @@
+use crate::image::Image;
+use crate::postprocess::{Frame, PostEffect};
+use crate::tonemap::ToneMapper;
+use crate::Color;
+
+// RGB space the renderer does its math in. Colors written in the scenes are linear sRGB and get
+// converted to it with convert_linear_srgb(), same as textures. Wider spaces like ACEScg mix
+// saturated colors (say, light bouncing between colored walls) more like the real world does
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColorSpace {
+    // Rec.709/sRGB primaries, D65 white
+    LinearSrgb,
+    // ACES AP1 primaries, D60 white
+    AcesCg,
+}
+
+impl ColorSpace {
+    // Rec.709 to AP1 with a Bradford D65 to D60 adaptation
+    const SRGB_TO_ACESCG: [[f32; 3]; 3] = [
+        [0.6130974, 0.3395231, 0.0473795],
+        [0.0701937, 0.9163539, 0.0134524],
+        [0.0206156, 0.1095698, 0.8698146],
+    ];
+    const ACESCG_TO_SRGB: [[f32; 3]; 3] = [
+        [1.705051, -0.6217921, -0.0832589],
+        [-0.1302564, 1.1408047, -0.0105483],
+        [-0.0240034, -0.128969, 1.1529724],
+    ];
+
+    pub fn convert_linear_srgb(self, color: Color) -> Color {
+        match self {
+            ColorSpace::LinearSrgb => color,
+            ColorSpace::AcesCg => transform(&Self::SRGB_TO_ACESCG, color),
+        }
+    }
+
+    pub fn to_linear_srgb(self, color: Color) -> Color {
+        match self {
+            ColorSpace::LinearSrgb => color,
+            ColorSpace::AcesCg => transform(&Self::ACESCG_TO_SRGB, color),
+        }
+    }
+}
+
+// How linear light is encoded into the values written to the image. Displays expect sRGB, video
+// expects Rec.709
+#[derive(Clone, Copy)]
+pub enum TransferFunction {
+    Srgb,
+    Rec709,
+    // Plain power curve, the renderer used to write Gamma(2.0)
+    Gamma(f32),
+    Linear,
+}
+
+impl TransferFunction {
+    pub fn encode(&self, color: Color) -> Color {
+        let mut result = color;
+
+        for value in result.e.iter_mut() {
+            let v = value.max(0.0);
+            *value = match self {
+                TransferFunction::Srgb => linear_to_srgb(v),
+                TransferFunction::Rec709 => {
+                    if v < 0.018 {
+                        4.5 * v
+                    } else {
+                        1.099 * v.powf(0.45) - 0.099
+                    }
+                }
+                TransferFunction::Gamma(gamma) => v.powf(1.0 / gamma),
+                TransferFunction::Linear => v,
+            };
+        }
+
+        result
+    }
+}
+
+// Turns the film's radiance into what gets written to the image: exposure, the post effects, then
+// tone mapping (done in linear sRGB, which is what the tone mappers expect) and finally the
+// transfer function
+pub struct ViewTransform {
+    pub exposure: f32,
+    pub effects: Vec<PostEffect>,
+    pub frame: Frame,
+    pub tone_mapper: ToneMapper,
+    pub working_space: ColorSpace,
+    pub transfer: TransferFunction,
+}
+
+impl ViewTransform {
+    pub fn apply(&self, image: &Image) -> Image {
+        let mut image = image.map(&|color| color * self.exposure);
+
+        for effect in &self.effects {
+            image = effect.apply(&image, &self.frame);
+        }
+
+        image.map(&|color| {
+            let linear = self.working_space.to_linear_srgb(color);
+            self.transfer.encode(self.tone_mapper.apply(linear))
+        })
+    }
+}
+
+// The sRGB curve (IEC 61966-2-1)
+pub fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        12.92 * v
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Multiplies a color by a 3x3 matrix, given row by row
+pub fn transform(m: &[[f32; 3]; 3], c: Color) -> Color {
+    Color::new(
+        Some(m[0][0] * c.x() + m[0][1] * c.y() + m[0][2] * c.z()),
+        Some(m[1][0] * c.x() + m[1][1] * c.y() + m[1][2] * c.z()),
+        Some(m[2][0] * c.x() + m[2][1] * c.y() + m[2][2] * c.z()),
+    )
+}
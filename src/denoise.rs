@@ -0,0 +1,265 @@
+use crate::aov::AovSample;
+use crate::film::Film;
+use crate::image::Image;
+use crate::{Color, Vec3};
+
+// Edge avoiding à-trous wavelet filter (Dammertz et al., with the variance guided weights of
+// SVGF). Every iteration blurs with a 5x5 B3 spline kernel whose taps are spread twice as far
+// apart as in the previous one, so a few cheap iterations cover a wide area. Taps are weighted
+// down when the AOVs say they're on a different surface (normal, depth, albedo) and when their
+// color differs more than the pixel's noise explains.
+//
+// Texture detail is kept by filtering the lighting only: colors are divided by the albedo before
+// filtering and multiplied back after. Needs the film's AOVs
+pub struct Denoiser {
+    pub iterations: i32,
+    // How different two colors can be, in standard deviations of the pixel's noise
+    pub color_sigma: f32,
+    // Exponent on the cosine between the normals, higher keeps edges sharper
+    pub normal_power: f32,
+    // Allowed depth difference relative to the pixel's depth, per step
+    pub depth_sigma: f32,
+    pub albedo_sigma: f32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Denoiser {
+        Denoiser {
+            iterations: 5,
+            color_sigma: 4.0,
+            normal_power: 64.0,
+            depth_sigma: 0.02,
+            albedo_sigma: 0.1,
+        }
+    }
+}
+
+impl Denoiser {
+    const KERNEL: [f32; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+    // Denoises the film's image
+    pub fn denoise(&self, film: &Film) -> Image {
+        let width = film.width();
+        let height = film.height();
+        let aovs: Vec<AovSample> = (0..height)
+            .flat_map(|j| (0..width).map(move |i| (i, j)))
+            .map(|(i, j)| film.aov(i, j))
+            .collect();
+        let aov = |i: i32, j: i32| &aovs[(j * width + i) as usize];
+        let mut normals = Vec::with_capacity(aovs.len());
+
+        for sample in &aovs {
+            normals.push(if sample.normal.len_squared() > 0.0 {
+                Vec3::unit_vector(sample.normal)
+            } else {
+                sample.normal
+            });
+        }
+
+        // Lighting with the albedo taken out
+        let mut color = Image::from_fn(width, height, &|i, j| {
+            demodulate(film.pixel(i, j), &aov(i, j).albedo)
+        });
+        // Variance of the pixel means, in the same units. The filter needs it to tell noise from
+        // detail, and it shrinks as pixels get averaged together
+        let mut variance: Vec<f32> = Vec::with_capacity(aovs.len());
+
+        for j in 0..height {
+            for i in 0..width {
+                let albedo = aov(i, j).albedo.luminance();
+                let scale = if albedo > MIN_ALBEDO { albedo } else { 1.0 };
+                let error = film.stats(i, j).error() / scale;
+                // Pixels with too few samples to tell are assumed to be all noise
+                let luminance = color.get(i, j).luminance();
+                variance.push(if error.is_finite() {
+                    error * error
+                } else {
+                    luminance * luminance
+                });
+            }
+        }
+
+        for iteration in 0..self.iterations {
+            let step = 1 << iteration;
+            // At low sample counts the variance of a single pixel is a poor guess (a pixel whose
+            // samples all came back black says it has none), its neighbourhood's is better
+            let smooth_variance = blur_3x3(&variance, width, height);
+            let mut filtered = Image::new(width, height);
+            let mut filtered_variance = vec![0.0; variance.len()];
+
+            for j in 0..height {
+                for i in 0..width {
+                    let index = (j * width + i) as usize;
+                    let center = aov(i, j);
+                    let center_color = color.get(i, j);
+                    let center_luminance = center_color.luminance();
+                    let mut sum = Color::new(None, None, None);
+                    let mut variance_sum = 0.0;
+                    let mut weight_sum = 0.0;
+
+                    for dy in -2..=2 {
+                        for dx in -2..=2 {
+                            let x = i + dx * step;
+                            let y = j + dy * step;
+
+                            if x < 0 || x >= width || y < 0 || y >= height {
+                                continue;
+                            }
+
+                            let tap = (y * width + x) as usize;
+                            let other = aov(x, y);
+                            let other_color = color.get(x, y);
+                            let mut weight = Self::KERNEL[dx.unsigned_abs() as usize]
+                                * Self::KERNEL[dy.unsigned_abs() as usize];
+
+                            if center.hit != other.hit {
+                                continue;
+                            }
+                            if center.hit {
+                                let cosine = normals[index].dot(&normals[tap]).max(0.0);
+                                let depth = (center.depth - other.depth).abs()
+                                    / (center.depth * self.depth_sigma * step as f32 + 1e-4);
+                                let albedo = (center.albedo - other.albedo).len_squared()
+                                    / (self.albedo_sigma * self.albedo_sigma);
+                                weight *= cosine.powf(self.normal_power) * (-depth - albedo).exp();
+                            }
+
+                            // Both sides' noise counts, otherwise a dark pixel would refuse the
+                            // bright neighbours its own samples just happened to miss
+                            let color_scale = self.color_sigma
+                                * (smooth_variance[index] + smooth_variance[tap]).sqrt()
+                                + 1e-4;
+                            let luminance = (center_luminance - other_color.luminance()).abs();
+                            weight *= (-luminance / color_scale).exp();
+
+                            sum += other_color * weight;
+                            variance_sum += weight * weight * variance[tap];
+                            weight_sum += weight;
+                        }
+                    }
+
+                    // Even the center tap gets no weight when the normal AOV averaged out to
+                    // nothing (the normals of the samples cancelling each other). Nothing to go
+                    // by then, the pixel stays as it is
+                    if weight_sum > 0.0 {
+                        filtered.set(i, j, sum / weight_sum);
+                        filtered_variance[index] = variance_sum / (weight_sum * weight_sum);
+                    } else {
+                        filtered.set(i, j, center_color);
+                        filtered_variance[index] = variance[index];
+                    }
+                }
+            }
+
+            color = filtered;
+            variance = filtered_variance;
+        }
+
+        Image::from_fn(width, height, &|i, j| {
+            remodulate(color.get(i, j), &aov(i, j).albedo)
+        })
+    }
+}
+
+fn blur_3x3(values: &[f32], width: i32, height: i32) -> Vec<f32> {
+    let kernel = [0.25, 0.5, 0.25];
+    let mut result = vec![0.0; values.len()];
+
+    for j in 0..height {
+        for i in 0..width {
+            let mut sum = 0.0;
+            let mut weight_sum = 0.0;
+
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let x = i + dx;
+                    let y = j + dy;
+
+                    if x < 0 || x >= width || y < 0 || y >= height {
+                        continue;
+                    }
+
+                    let weight = kernel[(dx + 1) as usize] * kernel[(dy + 1) as usize];
+                    sum += weight * values[(y * width + x) as usize];
+                    weight_sum += weight;
+                }
+            }
+
+            result[(j * width + i) as usize] = sum / weight_sum;
+        }
+    }
+
+    result
+}
+
+// Dark albedos would blow the noise up, those channels are left alone
+const MIN_ALBEDO: f32 = 0.01;
+
+fn demodulate(color: Color, albedo: &Color) -> Color {
+    let mut result = color;
+
+    for axis in 0..3 {
+        if albedo.e[axis] > MIN_ALBEDO {
+            result.e[axis] /= albedo.e[axis];
+        }
+    }
+
+    result
+}
+
+fn remodulate(color: Color, albedo: &Color) -> Color {
+    let mut result = color;
+
+    for axis in 0..3 {
+        if albedo.e[axis] > MIN_ALBEDO {
+            result.e[axis] *= albedo.e[axis];
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Filter;
+    use crate::Point3;
+
+    #[test]
+    fn pixels_without_a_normal_stay_finite() {
+        let mut film = Film::new(3, 3, Filter::Box(0.5));
+
+        for j in 0..3 {
+            for i in 0..3 {
+                for value in [0.2, 0.6] {
+                    let color = Color::new(Some(value), Some(value), Some(value));
+                    film.add_sample(i, j, (0.5, 0.5), color);
+                }
+                film.add_aov(
+                    i,
+                    j,
+                    &AovSample {
+                        albedo: Color::new(Some(0.5), Some(0.5), Some(0.5)),
+                        normal: Vec3::new(None, None, None),
+                        position: Point3::new(Some(i as f32), Some(j as f32), Some(-1.0)),
+                        depth: 1.0,
+                        uv: (0.0, 0.0),
+                        object_id: 1,
+                        material_id: 1,
+                        hit: true,
+                    },
+                );
+            }
+        }
+
+        let image = Denoiser::default().denoise(&film);
+
+        for j in 0..3 {
+            for i in 0..3 {
+                let pixel = image.get(i, j);
+                assert!(pixel.e.iter().all(|value| value.is_finite()));
+                assert!((pixel.x() - film.pixel(i, j).x()).abs() < 1e-5);
+            }
+        }
+    }
+}
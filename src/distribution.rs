@@ -0,0 +1,192 @@
+// Piecewise constant distribution over [0,1) made of equal width buckets, each one picked in
+// proportion to its value. Used to importance sample things given as tables, like the pixels of an
+// environment map. There has to be at least one bucket
+pub struct Distribution1D {
+    function: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    pub fn new(function: Vec<f32>) -> Option<Distribution1D> {
+        if function.is_empty() {
+            return None;
+        }
+
+        let count = function.len();
+        let mut cdf = vec![0.0; count + 1];
+
+        for i in 0..count {
+            cdf[i + 1] = cdf[i] + function[i].max(0.0) / count as f32;
+        }
+
+        let integral = cdf[count];
+
+        // Nothing to go by, every bucket is as likely
+        for (i, value) in cdf.iter_mut().enumerate() {
+            *value = if integral > 0.0 {
+                *value / integral
+            } else {
+                i as f32 / count as f32
+            };
+        }
+
+        Some(Distribution1D {
+            function,
+            cdf,
+            integral,
+        })
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    // A point in [0,1), its pdf and the bucket it's in
+    pub fn sample(&self, u: f32) -> (f32, f32, usize) {
+        let count = self.function.len();
+        // Last bucket whose cdf starts at or below u
+        let index = (self.cdf.partition_point(|&c| c <= u) - 1).min(count - 1);
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let offset = if width > 0.0 {
+            (u - self.cdf[index]) / width
+        } else {
+            0.0
+        };
+
+        (
+            ((index as f32 + offset) / count as f32).min(1.0 - f32::EPSILON),
+            self.pdf(index),
+            index,
+        )
+    }
+
+    pub fn pdf(&self, index: usize) -> f32 {
+        if self.integral > 0.0 {
+            self.function[index].max(0.0) / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+// Same over [0,1)^2, from a table of width x height values given row by row. A row is picked by
+// its total first, then a column within it
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    // None when the table is empty or has fewer than width x height values
+    pub fn new(values: &[f32], width: usize, height: usize) -> Option<Distribution2D> {
+        if width == 0 || values.len() < width * height {
+            return None;
+        }
+
+        let rows: Vec<Distribution1D> = values
+            .chunks_exact(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect::<Option<_>>()?;
+        let marginal = Distribution1D::new(rows.iter().map(|row| row.integral()).collect())?;
+
+        Some(Distribution2D { rows, marginal })
+    }
+
+    // A point (x, y), x along the rows, and its pdf
+    pub fn sample(&self, u: (f32, f32)) -> ((f32, f32), f32) {
+        let (y, row_pdf, row) = self.marginal.sample(u.1);
+        let (x, column_pdf, _) = self.rows[row].sample(u.0);
+
+        ((x, y), row_pdf * column_pdf)
+    }
+
+    pub fn pdf(&self, point: (f32, f32)) -> f32 {
+        let height = self.rows.len();
+        let row = ((point.1 * height as f32) as usize).min(height - 1);
+        let width = self.rows[row].function.len();
+        let column = ((point.0 * width as f32) as usize).min(width - 1);
+
+        self.marginal.pdf(row) * self.rows[row].pdf(column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distribution_1d_follows_the_function() {
+        let distribution = Distribution1D::new(vec![1.0, 0.0, 3.0, 4.0]).unwrap();
+
+        assert_eq!(distribution.integral(), 2.0);
+        assert_eq!(distribution.pdf(1), 0.0);
+
+        for i in 0..1000 {
+            let (x, pdf, index) = distribution.sample(i as f32 / 1000.0);
+
+            assert!((0.0..1.0).contains(&x));
+            assert_eq!(index, (x * 4.0) as usize);
+            assert_ne!(index, 1, "a bucket with no weight got sampled");
+            assert_eq!(pdf, distribution.pdf(index));
+        }
+    }
+
+    #[test]
+    fn distribution_1d_without_weight_is_uniform() {
+        let distribution = Distribution1D::new(vec![0.0; 4]).unwrap();
+
+        assert_eq!(distribution.sample(0.6), (0.6, 1.0, 2));
+    }
+
+    #[test]
+    fn empty_distributions_are_rejected() {
+        assert!(Distribution1D::new(vec![]).is_none());
+        assert!(Distribution2D::new(&[], 0, 0).is_none());
+        assert!(Distribution2D::new(&[1.0; 4], 4, 0).is_none());
+        assert!(Distribution2D::new(&[1.0; 4], 0, 4).is_none());
+        assert!(Distribution2D::new(&[1.0; 4], 2, 3).is_none());
+    }
+
+    #[test]
+    fn distribution_2d_pdf_matches_samples() {
+        let (width, height) = (4, 3);
+        let values: Vec<f32> = (0..width * height).map(|i| (i % 5) as f32).collect();
+        let distribution = Distribution2D::new(&values, width, height).unwrap();
+
+        // The pdf integrates to 1 over the unit square
+        let mut integral = 0.0;
+        for y in 0..height {
+            for x in 0..width {
+                let point = (
+                    (x as f32 + 0.5) / width as f32,
+                    (y as f32 + 0.5) / height as f32,
+                );
+                integral += distribution.pdf(point) / (width * height) as f32;
+            }
+        }
+        assert!(
+            (integral - 1.0).abs() < 1e-5,
+            "pdf integrates to {}",
+            integral
+        );
+
+        for i in 0..50 {
+            for j in 0..50 {
+                let (point, pdf) = distribution.sample((i as f32 / 50.0, j as f32 / 50.0));
+                let (x, y) = (
+                    (point.0 * width as f32) as usize,
+                    (point.1 * height as f32) as usize,
+                );
+
+                assert!(pdf > 0.0);
+                assert!(
+                    values[y * width + x] > 0.0,
+                    "a cell with no weight got sampled"
+                );
+                assert!((pdf - distribution.pdf(point)).abs() < 1e-5);
+            }
+        }
+    }
+}
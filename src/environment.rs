@@ -0,0 +1,176 @@
+use crate::background::Background;
+use crate::color::{srgb_to_linear, ColorSpace};
+use crate::distribution::Distribution2D;
+use crate::rtweekend::{degrees_to_radians, PI};
+use crate::texture::texel_color;
+use crate::{Color, Vec3};
+use stb_image::image::LoadResult;
+
+// Light coming from infinitely far away in every direction, read from an equirectangular
+// (latitude/longitude) image, ideally an HDR (.hdr) one so the sun and bright sky keep their real
+// intensity. Directions map to the image like UVs on a sphere: the top row is straight up, and
+// the middle of the image looks down +X. 'rotation' turns the map around the Y axis, in degrees,
+// and 'intensity' scales it.
+//
+// Directions are importance sampled by how bright the map is there, so lighting from a small sun
+// in a big dark map is found right away instead of by chance
+pub struct EnvironmentMap {
+    data: Vec<Color>,
+    width: i32,
+    height: i32,
+    rotation: f32,
+    intensity: f32,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    pub fn new(
+        filename: &str,
+        working_space: ColorSpace,
+        rotation: Option<f32>,
+        intensity: Option<f32>,
+    ) -> Result<EnvironmentMap, String> {
+        // HDR files hold linear values, anything else is 8 bit sRGB
+        let (data, width, height) = match stb_image::image::load(filename) {
+            LoadResult::Error(e) => return Err(e),
+            LoadResult::ImageF32(image) => {
+                let data: Vec<Color> = image
+                    .data
+                    .chunks_exact(image.depth)
+                    .map(|texel| texel_color(texel, &|value| value))
+                    .collect();
+                (data, image.width, image.height)
+            }
+            LoadResult::ImageU8(image) => {
+                let data: Vec<Color> = image
+                    .data
+                    .chunks_exact(image.depth)
+                    .map(|texel| texel_color(texel, &|value| srgb_to_linear(value as f32 / 255.0)))
+                    .collect();
+                (data, image.width, image.height)
+            }
+        };
+        let data = data
+            .into_iter()
+            .map(|color| working_space.convert_linear_srgb(color))
+            .collect();
+
+        EnvironmentMap::from_data(data, width as i32, height as i32, rotation, intensity)
+    }
+
+    // Bakes a procedural environment (like PhysicalSky) into a 'width' x 'height' map, so it can
+    // be importance sampled the same way. 'radiance' gets the direction through every pixel's
+    // center and should give colors in the working space
+    pub fn from_fn(
+        width: i32,
+        height: i32,
+        radiance: &dyn Fn(&Vec3) -> Color,
+    ) -> Result<EnvironmentMap, String> {
+        let mut data = Vec::with_capacity((width * height) as usize);
+
+        for j in 0..height {
+            for i in 0..width {
+                let direction = map_direction(
+                    (i as f32 + 0.5) / width as f32,
+                    (j as f32 + 0.5) / height as f32,
+                    0.0,
+                );
+                data.push(radiance(&direction));
+            }
+        }
+
+        EnvironmentMap::from_data(data, width, height, None, None)
+    }
+
+    fn from_data(
+        data: Vec<Color>,
+        width: i32,
+        height: i32,
+        rotation: Option<f32>,
+        intensity: Option<f32>,
+    ) -> Result<EnvironmentMap, String> {
+        // Rows near the poles cover less of the sphere, their pixels get picked less
+        let mut weights = Vec::with_capacity(data.len());
+        for row in 0..height {
+            let sin_theta = (PI * (row as f32 + 0.5) / height as f32).sin();
+
+            for column in 0..width {
+                weights.push(data[(row * width + column) as usize].luminance() * sin_theta);
+            }
+        }
+
+        let distribution = match Distribution2D::new(&weights, width as usize, height as usize) {
+            Some(distribution) => distribution,
+            None => return Err(format!("a {}x{} map is empty", width, height)),
+        };
+
+        Ok(EnvironmentMap {
+            distribution,
+            data,
+            width,
+            height,
+            rotation: degrees_to_radians(rotation.unwrap_or(0.0)),
+            intensity: intensity.unwrap_or(1.0),
+        })
+    }
+
+    // Unit direction to map coordinates, v going down from the top
+    fn to_map(&self, direction: &Vec3) -> (f32, f32) {
+        let phi = (-direction.z()).atan2(direction.x()) + PI - self.rotation;
+        let u = (phi / (2.0 * PI)).rem_euclid(1.0);
+        let v = direction.y().clamp(-1.0, 1.0).acos() / PI;
+
+        (u, v)
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn radiance(&self, direction: &Vec3) -> Color {
+        let (u, v) = self.to_map(&Vec3::unit_vector(*direction));
+        let i = ((u * self.width as f32) as i32).clamp(0, self.width - 1);
+        let j = ((v * self.height as f32) as i32).clamp(0, self.height - 1);
+
+        self.data[(j * self.width + i) as usize] * self.intensity
+    }
+
+    fn importance_sampled(&self) -> bool {
+        true
+    }
+
+    // Picked in proportion to the map's brightness
+    fn sample(&self, u: (f32, f32)) -> (Vec3, f32) {
+        let ((x, y), map_pdf) = self.distribution.sample(u);
+        let direction = map_direction(x, y, self.rotation);
+        let sin_theta = (PI * y).sin();
+
+        if sin_theta <= 0.0 {
+            return (direction, 0.0);
+        }
+
+        // The map's [0,1) square covers 2 pi by pi radians, squeezed by sin(theta)
+        (direction, map_pdf / (2.0 * PI * PI * sin_theta))
+    }
+
+    fn pdf(&self, direction: &Vec3) -> f32 {
+        let (u, v) = self.to_map(&Vec3::unit_vector(*direction));
+        let sin_theta = (PI * v).sin();
+
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        self.distribution.pdf((u, v)) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+// Map coordinates back to a unit direction
+fn map_direction(u: f32, v: f32, rotation: f32) -> Vec3 {
+    let phi = 2.0 * PI * u + rotation - PI;
+    let theta = PI * v;
+
+    Vec3::new(
+        Some(theta.sin() * phi.cos()),
+        Some(theta.cos()),
+        Some(-theta.sin() * phi.sin()),
+    )
+}
@@ -0,0 +1,191 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+
+// Minimal OpenEXR writer: a single part, scanline image with uncompressed 32 bit float channels.
+// Channel names can have a layer prefix like "albedo.R", compositing software groups those into
+// layers. Each channel holds width * height values going row by row starting at the top
+pub fn write_exr(
+    filename: &str,
+    width: i32,
+    height: i32,
+    channels: &[(&str, Vec<f32>)],
+) -> io::Result<()> {
+    // EXR wants the channels sorted by name, both in the header and in the pixel data
+    let mut channels: Vec<&(&str, Vec<f32>)> = channels.iter().collect();
+    channels.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01]);
+    header.extend_from_slice(&2u32.to_le_bytes());
+
+    let mut channel_list = Vec::new();
+    for (name, _) in &channels {
+        channel_list.extend_from_slice(name.as_bytes());
+        channel_list.push(0);
+        // Pixel type FLOAT, not perceptually linear, 3 reserved bytes, no subsampling
+        channel_list.extend_from_slice(&2i32.to_le_bytes());
+        channel_list.extend_from_slice(&[0, 0, 0, 0]);
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channel_list.push(0);
+    write_attribute(&mut header, "channels", "chlist", &channel_list);
+
+    // No compression
+    write_attribute(&mut header, "compression", "compression", &[0]);
+
+    let mut window = Vec::new();
+    for value in &[0, 0, width - 1, height - 1] {
+        window.extend_from_slice(&value.to_le_bytes());
+    }
+    write_attribute(&mut header, "dataWindow", "box2i", &window);
+    write_attribute(&mut header, "displayWindow", "box2i", &window);
+
+    // Increasing Y, i.e. top to bottom
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    write_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1.0f32.to_le_bytes(),
+    );
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    write_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1.0f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    // Every scanline is its own chunk, the offset table says where each one starts
+    let line_size = width as usize * channels.len() * 4;
+    let first_line = header.len() + height as usize * 8;
+
+    let mut out = BufWriter::new(File::create(filename)?);
+    out.write_all(&header)?;
+
+    for y in 0..height as usize {
+        let offset = first_line + y * (8 + line_size);
+        out.write_all(&(offset as u64).to_le_bytes())?;
+    }
+
+    for y in 0..height as usize {
+        out.write_all(&(y as i32).to_le_bytes())?;
+        out.write_all(&(line_size as i32).to_le_bytes())?;
+
+        for (_, values) in &channels {
+            for value in &values[y * width as usize..(y + 1) * width as usize] {
+                out.write_all(&value.to_le_bytes())?;
+            }
+        }
+    }
+
+    out.flush()
+}
+
+fn write_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    fn read_i32(bytes: &[u8], at: usize) -> i32 {
+        i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    fn read_string(bytes: &[u8], at: &mut usize) -> String {
+        let end = *at + bytes[*at..].iter().position(|&b| b == 0).unwrap();
+        let string = String::from_utf8(bytes[*at..end].to_vec()).unwrap();
+        *at = end + 1;
+
+        string
+    }
+
+    #[test]
+    fn header_and_scanlines_layout() {
+        let filename = std::env::temp_dir().join(format!("exr_test_{}.exr", std::process::id()));
+        let filename = filename.to_str().unwrap();
+        let channels = [
+            ("R", vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]),
+            ("G", vec![-1.0, -2.0, -3.0, -4.0, -5.0, -6.0]),
+        ];
+        write_exr(filename, 3, 2, &channels).unwrap();
+        let bytes = std::fs::read(filename).unwrap();
+        std::fs::remove_file(filename).unwrap();
+
+        assert_eq!(bytes[0..4], [0x76, 0x2f, 0x31, 0x01]);
+        assert_eq!(read_i32(&bytes, 4), 2);
+
+        let mut at = 8;
+        let mut names = Vec::new();
+        while bytes[at] != 0 {
+            let name = read_string(&bytes, &mut at);
+            let kind = read_string(&bytes, &mut at);
+            let size = read_i32(&bytes, at) as usize;
+            at += 4;
+
+            if name == "channels" {
+                assert_eq!(kind, "chlist");
+                let mut channel_at = at;
+                // Sorted by name
+                assert_eq!(read_string(&bytes, &mut channel_at), "G");
+                channel_at += 16;
+                assert_eq!(read_string(&bytes, &mut channel_at), "R");
+                channel_at += 16;
+                assert_eq!(bytes[channel_at], 0);
+                assert_eq!(channel_at + 1, at + size);
+            }
+            if name == "dataWindow" {
+                assert_eq!(kind, "box2i");
+                let window: Vec<i32> = (0..4).map(|i| read_i32(&bytes, at + i * 4)).collect();
+                assert_eq!(window, [0, 0, 2, 1]);
+            }
+
+            names.push(name);
+            at += size;
+        }
+        at += 1;
+
+        assert_eq!(
+            names,
+            [
+                "channels",
+                "compression",
+                "dataWindow",
+                "displayWindow",
+                "lineOrder",
+                "pixelAspectRatio",
+                "screenWindowCenter",
+                "screenWindowWidth"
+            ]
+        );
+
+        // Offset table, then one chunk per scanline: y, size and the channels one after another
+        let line_size = 3 * 2 * 4;
+        assert_eq!(bytes.len(), at + 2 * 8 + 2 * (8 + line_size));
+        for y in 0..2 {
+            let offset = u64::from_le_bytes(bytes[at + y * 8..at + y * 8 + 8].try_into().unwrap());
+            let offset = offset as usize;
+
+            assert_eq!(offset, at + 2 * 8 + y * (8 + line_size));
+            assert_eq!(read_i32(&bytes, offset), y as i32);
+            assert_eq!(read_i32(&bytes, offset + 4), line_size as i32);
+
+            let first_green =
+                f32::from_le_bytes(bytes[offset + 8..offset + 12].try_into().unwrap());
+            let first_red = f32::from_le_bytes(bytes[offset + 20..offset + 24].try_into().unwrap());
+            assert_eq!(first_green, channels[1].1[y * 3]);
+            assert_eq!(first_red, channels[0].1[y * 3]);
+        }
+    }
+}
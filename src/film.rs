@@ -0,0 +1,653 @@
+use crate::adaptive::{write_heatmap, PixelStats};
+use crate::aov::{id_color, AovPixel, AovSample};
+use crate::color::{ColorSpace, TransferFunction};
+use crate::exr::write_exr;
+use crate::filter::Filter;
+use crate::firefly::{FireflyStats, FireflySuppression};
+use crate::image::Image;
+use crate::rtweekend::INFINITY;
+use crate::{Color, Point3, Vec3};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+// What else a checkpoint has to agree with, besides the size and filter, to be resumed: where the
+// film sits in the full frame (the crop window, like Frame's offsets) and the seed the samples came from
+#[derive(Clone, Copy, PartialEq)]
+pub struct CheckpointSettings {
+    pub x_offset: i32,
+    pub y_offset: i32,
+    pub seed: u64,
+}
+
+// Accumulates the samples of a render. Keeps the filter weighted sum of the samples around every
+// pixel plus the statistics of the samples taken in it, so passes can keep adding samples and the
+// image can be written (or saved and resumed) at any point. Pixels are indexed like the render
+// loop, 'j' goes bottom to top
+pub struct Film {
+    width: i32,
+    height: i32,
+    filter: Filter,
+    firefly_suppression: FireflySuppression,
+    firefly_stats: FireflyStats,
+    pixels: Vec<Color>,
+    weights: Vec<f32>,
+    stats: Vec<PixelStats>,
+    aovs: Vec<AovPixel>,
+}
+
+impl Film {
+    const CHECKPOINT_MAGIC: &'static [u8; 8] = b"SILMFILM";
+    const CHECKPOINT_VERSION: u32 = 5;
+    // What save_checkpoint() writes per pixel
+    const CHECKPOINT_PIXEL_BYTES: u64 = 92;
+
+    pub fn new(width: i32, height: i32, filter: Filter) -> Film {
+        Film {
+            width,
+            height,
+            filter,
+            firefly_suppression: FireflySuppression::default(),
+            firefly_stats: FireflyStats::default(),
+            pixels: vec![Color::new(None, None, None); (width * height) as usize],
+            weights: vec![0.0; (width * height) as usize],
+            stats: vec![PixelStats::default(); (width * height) as usize],
+            aovs: vec![AovPixel::default(); (width * height) as usize],
+        }
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    pub fn set_firefly_suppression(&mut self, firefly_suppression: FireflySuppression) {
+        self.firefly_suppression = firefly_suppression;
+    }
+
+    pub fn firefly_stats(&self) -> &FireflyStats {
+        &self.firefly_stats
+    }
+
+    fn index(&self, i: i32, j: i32) -> usize {
+        (j * self.width + i) as usize
+    }
+
+    // Adds a sample taken in pixel (i, j), 'offset' being where in the pixel (0 to 1 on both axes).
+    // Fireflies are suppressed first, if set up. It's splatted onto every pixel the filter
+    // reaches, only the statistics stay with (i, j). Pixels at the edge of the film miss the
+    // samples that would have come from outside
+    pub fn add_sample(&mut self, i: i32, j: i32, offset: (f32, f32), color: Color) {
+        let index = self.index(i, j);
+        let luminance = color.luminance();
+        let color =
+            self.firefly_suppression
+                .apply(color, &self.stats[index], &mut self.firefly_stats);
+        self.stats[index].add(luminance);
+
+        let x = i as f32 + offset.0;
+        let y = j as f32 + offset.1;
+        let radius = self.filter.radius();
+        // Pixels whose center is within the radius, counting a sample right on the edge of a box
+        // filter for one pixel only
+        let x0 = ((x - 0.5 - radius).floor() as i32 + 1).max(0);
+        let x1 = ((x - 0.5 + radius).floor() as i32).min(self.width - 1);
+        let y0 = ((y - 0.5 - radius).floor() as i32 + 1).max(0);
+        let y1 = ((y - 0.5 + radius).floor() as i32).min(self.height - 1);
+
+        for py in y0..=y1 {
+            for px in x0..=x1 {
+                let weight = self
+                    .filter
+                    .evaluate(x - (px as f32 + 0.5), y - (py as f32 + 0.5));
+
+                if weight != 0.0 {
+                    let index = self.index(px, py);
+                    self.pixels[index] += color * weight;
+                    self.weights[index] += weight;
+                }
+            }
+        }
+    }
+
+    pub fn add_aov(&mut self, i: i32, j: i32, sample: &AovSample) {
+        let index = self.index(i, j);
+        self.aovs[index].add(sample);
+    }
+
+    pub fn aov(&self, i: i32, j: i32) -> AovSample {
+        self.aovs[self.index(i, j)].average()
+    }
+
+    pub fn stats(&self, i: i32, j: i32) -> &PixelStats {
+        &self.stats[self.index(i, j)]
+    }
+
+    // Filter weighted average of the samples around a pixel
+    pub fn pixel(&self, i: i32, j: i32) -> Color {
+        let index = self.index(i, j);
+        let weight = self.weights[index];
+
+        // No samples yet, or negative filter lobes cancelled them out
+        if weight <= 0.0 {
+            return Color::new(None, None, None);
+        }
+
+        let color = self.pixels[index] / weight;
+
+        Color::new(
+            Some(color.x().max(0.0)),
+            Some(color.y().max(0.0)),
+            Some(color.z().max(0.0)),
+        )
+    }
+
+    // The average radiance of every pixel
+    pub fn image(&self) -> Image {
+        Image::from_fn(self.width, self.height, &|i, j| self.pixel(i, j))
+    }
+
+    fn save_image(&self, filename: &str, color: &dyn Fn(i32, i32) -> Color) -> io::Result<()> {
+        Image::from_fn(self.width, self.height, color).save_ppm(filename)
+    }
+
+    // One value per pixel, row by row starting at the top, as EXR channels want it
+    fn channel(&self, value: &dyn Fn(i32, i32) -> f32) -> Vec<f32> {
+        let mut values = Vec::with_capacity(self.pixels.len());
+
+        for j in (0..self.height).rev() {
+            for i in 0..self.width {
+                values.push(value(i, j));
+            }
+        }
+
+        values
+    }
+
+    // Saves the beauty pass (in linear radiance, scaled by 'exposure') and every AOV as layers of
+    // a single EXR file
+    pub fn save_exr(&self, filename: &str, exposure: f32) -> io::Result<()> {
+        let channels = [
+            ("R", self.channel(&|i, j| self.pixel(i, j).x() * exposure)),
+            ("G", self.channel(&|i, j| self.pixel(i, j).y() * exposure)),
+            ("B", self.channel(&|i, j| self.pixel(i, j).z() * exposure)),
+            ("albedo.R", self.channel(&|i, j| self.aov(i, j).albedo.x())),
+            ("albedo.G", self.channel(&|i, j| self.aov(i, j).albedo.y())),
+            ("albedo.B", self.channel(&|i, j| self.aov(i, j).albedo.z())),
+            ("normal.X", self.channel(&|i, j| self.aov(i, j).normal.x())),
+            ("normal.Y", self.channel(&|i, j| self.aov(i, j).normal.y())),
+            ("normal.Z", self.channel(&|i, j| self.aov(i, j).normal.z())),
+            ("depth.Z", self.channel(&|i, j| self.aov(i, j).depth)),
+            (
+                "position.X",
+                self.channel(&|i, j| self.aov(i, j).position.x()),
+            ),
+            (
+                "position.Y",
+                self.channel(&|i, j| self.aov(i, j).position.y()),
+            ),
+            (
+                "position.Z",
+                self.channel(&|i, j| self.aov(i, j).position.z()),
+            ),
+            ("uv.U", self.channel(&|i, j| self.aov(i, j).uv.0)),
+            ("uv.V", self.channel(&|i, j| self.aov(i, j).uv.1)),
+            (
+                "id.object",
+                self.channel(&|i, j| self.aov(i, j).object_id as f32),
+            ),
+            (
+                "id.material",
+                self.channel(&|i, j| self.aov(i, j).material_id as f32),
+            ),
+        ];
+
+        write_exr(filename, self.width, self.height, &channels)
+    }
+
+    // Saves every AOV as its own PPM, named '<prefix>_<aov>.ppm'. Values are remapped so they
+    // can be looked at: normals from [-1,1], depth and position relative to the nearest and
+    // farthest hits, IDs as random colors. Albedo is in 'working_space' and gets shown as sRGB
+    pub fn save_aov_images(&self, prefix: &str, working_space: ColorSpace) -> io::Result<()> {
+        let mut min = Point3::new(Some(INFINITY), Some(INFINITY), Some(INFINITY));
+        let mut max = -min;
+        let mut max_depth: f32 = 0.0;
+
+        for j in 0..self.height {
+            for i in 0..self.width {
+                let aov = self.aov(i, j);
+                if aov.hit {
+                    for axis in 0..3 {
+                        min.e[axis] = min.e[axis].min(aov.position.e[axis]);
+                        max.e[axis] = max.e[axis].max(aov.position.e[axis]);
+                    }
+                    max_depth = max_depth.max(aov.depth);
+                }
+            }
+        }
+
+        let extent = max - min;
+        let half = Vec3::new(Some(0.5), Some(0.5), Some(0.5));
+        let hit_only = |i: i32, j: i32, color: Color| {
+            if self.aov(i, j).hit {
+                color
+            } else {
+                Color::new(None, None, None)
+            }
+        };
+
+        self.save_image(&format!("{}_albedo.ppm", prefix), &|i, j| {
+            TransferFunction::Srgb.encode(working_space.to_linear_srgb(self.aov(i, j).albedo))
+        })?;
+        self.save_image(&format!("{}_normal.ppm", prefix), &|i, j| {
+            hit_only(i, j, self.aov(i, j).normal * 0.5 + half)
+        })?;
+        self.save_image(&format!("{}_depth.ppm", prefix), &|i, j| {
+            let depth = 1.0 - self.aov(i, j).depth / max_depth.max(f32::EPSILON);
+            hit_only(i, j, Color::new(Some(depth), Some(depth), Some(depth)))
+        })?;
+        self.save_image(&format!("{}_position.ppm", prefix), &|i, j| {
+            let p = self.aov(i, j).position - min;
+            hit_only(
+                i,
+                j,
+                Color::new(
+                    Some(p.x() / extent.x().max(f32::EPSILON)),
+                    Some(p.y() / extent.y().max(f32::EPSILON)),
+                    Some(p.z() / extent.z().max(f32::EPSILON)),
+                ),
+            )
+        })?;
+        self.save_image(&format!("{}_uv.ppm", prefix), &|i, j| {
+            let (u, v) = self.aov(i, j).uv;
+            hit_only(i, j, Color::new(Some(u), Some(v), None))
+        })?;
+        self.save_image(&format!("{}_object_id.ppm", prefix), &|i, j| {
+            id_color(self.aov(i, j).object_id)
+        })?;
+        self.save_image(&format!("{}_material_id.ppm", prefix), &|i, j| {
+            id_color(self.aov(i, j).material_id)
+        })
+    }
+
+    // See adaptive.rs. 'max_samples' is the sample count that maps to the hottest color
+    pub fn save_heatmap(&self, filename: &str, max_samples: i32) -> io::Result<()> {
+        let mut counts = Vec::with_capacity(self.stats.len());
+
+        for j in (0..self.height).rev() {
+            for i in 0..self.width {
+                counts.push(self.stats(i, j).count());
+            }
+        }
+
+        write_heatmap(filename, &counts, self.width, self.height, max_samples)
+    }
+
+    // Saves everything needed to resume the render later: accumulated radiance and sample
+    // statistics for every pixel, in a small binary format. The file is written next to the
+    // destination first and then renamed over it, so an interrupted save never leaves a broken
+    // checkpoint behind
+    pub fn save_checkpoint(&self, filename: &str, settings: &CheckpointSettings) -> io::Result<()> {
+        let temp_filename = format!("{}.tmp", filename);
+        let mut out = BufWriter::new(File::create(&temp_filename)?);
+        out.write_all(Self::CHECKPOINT_MAGIC)?;
+        out.write_all(&Self::CHECKPOINT_VERSION.to_le_bytes())?;
+        out.write_all(&self.width.to_le_bytes())?;
+        out.write_all(&self.height.to_le_bytes())?;
+        out.write_all(&settings.x_offset.to_le_bytes())?;
+        out.write_all(&settings.y_offset.to_le_bytes())?;
+        out.write_all(&settings.seed.to_le_bytes())?;
+        let (filter_kind, filter_parameters) = self.filter.encode();
+        out.write_all(&filter_kind.to_le_bytes())?;
+        for value in &filter_parameters {
+            out.write_all(&value.to_le_bytes())?;
+        }
+        out.write_all(&self.firefly_stats.samples.to_le_bytes())?;
+        out.write_all(&self.firefly_stats.clamped.to_le_bytes())?;
+        out.write_all(&self.firefly_stats.rejected.to_le_bytes())?;
+        out.write_all(&self.firefly_stats.total_luminance.to_le_bytes())?;
+        out.write_all(&self.firefly_stats.removed_luminance.to_le_bytes())?;
+
+        for index in 0..self.pixels.len() {
+            let stats = &self.stats[index];
+            let aov = &self.aovs[index];
+            write_vec3(&mut out, &self.pixels[index])?;
+            out.write_all(&self.weights[index].to_le_bytes())?;
+            out.write_all(&stats.count.to_le_bytes())?;
+            out.write_all(&stats.mean.to_le_bytes())?;
+            out.write_all(&stats.m2.to_le_bytes())?;
+            write_vec3(&mut out, &aov.albedo)?;
+            write_vec3(&mut out, &aov.normal)?;
+            write_vec3(&mut out, &aov.position)?;
+            out.write_all(&aov.depth.to_le_bytes())?;
+            out.write_all(&aov.uv.0.to_le_bytes())?;
+            out.write_all(&aov.uv.1.to_le_bytes())?;
+            out.write_all(&aov.object_id.to_le_bytes())?;
+            out.write_all(&aov.material_id.to_le_bytes())?;
+            out.write_all(&aov.samples.to_le_bytes())?;
+            out.write_all(&aov.hits.to_le_bytes())?;
+        }
+
+        out.flush()?;
+        drop(out);
+        fs::rename(&temp_filename, filename)
+    }
+
+    // The filter and settings have to be the ones the checkpoint was rendered with, it's an error
+    // otherwise since the samples couldn't be mixed with new ones
+    pub fn load_checkpoint(
+        filename: &str,
+        filter: Filter,
+        settings: &CheckpointSettings,
+    ) -> io::Result<Film> {
+        let mut input = BufReader::new(File::open(filename)?);
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic)?;
+
+        if &magic != Self::CHECKPOINT_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a film checkpoint", filename),
+            ));
+        }
+
+        let version = read_u32(&mut input)?;
+
+        if version != Self::CHECKPOINT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported checkpoint version {}", version),
+            ));
+        }
+
+        let width = read_i32(&mut input)?;
+        let height = read_i32(&mut input)?;
+        // Check the size against the file before allocating anything for it, a damaged file
+        // could ask for any amount
+        let pixel_count = match width.checked_mul(height) {
+            Some(count) if width > 0 && height > 0 => count as u64,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} has an invalid size {}x{}", filename, width, height),
+                ))
+            }
+        };
+
+        if pixel_count * Self::CHECKPOINT_PIXEL_BYTES > input.get_ref().metadata()?.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is too short for a {}x{} film", filename, width, height),
+            ));
+        }
+
+        let saved_settings = CheckpointSettings {
+            x_offset: read_i32(&mut input)?,
+            y_offset: read_i32(&mut input)?,
+            seed: read_u64(&mut input)?,
+        };
+
+        if saved_settings != *settings {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} was rendered at offset ({}, {}) with seed {}, not at ({}, {}) with seed {}",
+                    filename,
+                    saved_settings.x_offset,
+                    saved_settings.y_offset,
+                    saved_settings.seed,
+                    settings.x_offset,
+                    settings.y_offset,
+                    settings.seed
+                ),
+            ));
+        }
+
+        let filter_kind = read_u32(&mut input)?;
+        let filter_parameters = [
+            read_f32(&mut input)?,
+            read_f32(&mut input)?,
+            read_f32(&mut input)?,
+        ];
+
+        if (filter_kind, filter_parameters) != filter.encode() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} was rendered with a different filter", filename),
+            ));
+        }
+
+        let mut film = Film::new(width, height, filter);
+        film.firefly_stats = FireflyStats {
+            samples: read_u64(&mut input)?,
+            clamped: read_u64(&mut input)?,
+            rejected: read_u64(&mut input)?,
+            total_luminance: read_f64(&mut input)?,
+            removed_luminance: read_f64(&mut input)?,
+        };
+
+        for index in 0..(width * height) as usize {
+            film.pixels[index] = read_vec3(&mut input)?;
+            film.weights[index] = read_f32(&mut input)?;
+            film.stats[index] = PixelStats {
+                count: read_i32(&mut input)?,
+                mean: read_f32(&mut input)?,
+                m2: read_f32(&mut input)?,
+            };
+            film.aovs[index] = AovPixel {
+                albedo: read_vec3(&mut input)?,
+                normal: read_vec3(&mut input)?,
+                position: read_vec3(&mut input)?,
+                depth: read_f32(&mut input)?,
+                uv: (read_f32(&mut input)?, read_f32(&mut input)?),
+                object_id: read_u32(&mut input)?,
+                material_id: read_u32(&mut input)?,
+                samples: read_i32(&mut input)?,
+                hits: read_i32(&mut input)?,
+            };
+        }
+
+        Ok(film)
+    }
+}
+
+fn write_vec3(out: &mut dyn Write, v: &Vec3) -> io::Result<()> {
+    for value in &v.e {
+        out.write_all(&value.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+fn read_vec3(input: &mut dyn Read) -> io::Result<Vec3> {
+    Ok(Vec3::new(
+        Some(read_f32(input)?),
+        Some(read_f32(input)?),
+        Some(read_f32(input)?),
+    ))
+}
+
+fn read_u32(input: &mut dyn Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_i32(input: &mut dyn Read) -> io::Result<i32> {
+    let mut bytes = [0u8; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(i32::from_le_bytes(bytes))
+}
+
+fn read_f32(input: &mut dyn Read) -> io::Result<f32> {
+    let mut bytes = [0u8; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+fn read_u64(input: &mut dyn Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f64(input: &mut dyn Read) -> io::Result<f64> {
+    let mut bytes = [0u8; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: CheckpointSettings = CheckpointSettings {
+        x_offset: 10,
+        y_offset: 20,
+        seed: 7,
+    };
+
+    fn temp_filename(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("{}_{}", std::process::id(), name));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn checkpoint_round_trip() {
+        let filename = temp_filename("film_test.checkpoint");
+        let mut film = Film::new(3, 2, Filter::Tent(1.0));
+
+        for j in 0..2 {
+            for i in 0..3 {
+                let value = (j * 3 + i) as f32;
+                film.add_sample(
+                    i,
+                    j,
+                    (0.3, 0.6),
+                    Color::new(Some(value), Some(0.5), Some(2.0)),
+                );
+                film.add_aov(
+                    i,
+                    j,
+                    &AovSample {
+                        albedo: Color::new(Some(0.2), Some(value), Some(0.4)),
+                        normal: Vec3::new(None, Some(1.0), None),
+                        position: Point3::new(Some(value), Some(-1.0), Some(3.0)),
+                        depth: value + 1.0,
+                        uv: (0.25, 0.75),
+                        object_id: i as u32 + 1,
+                        material_id: j as u32 + 1,
+                        hit: true,
+                    },
+                );
+            }
+        }
+
+        film.save_checkpoint(&filename, &SETTINGS).unwrap();
+        let loaded = Film::load_checkpoint(&filename, Filter::Tent(1.0), &SETTINGS).unwrap();
+        fs::remove_file(&filename).unwrap();
+
+        assert_eq!((loaded.width(), loaded.height()), (3, 2));
+        assert_eq!(loaded.firefly_stats().samples, film.firefly_stats().samples);
+        assert_eq!(
+            loaded.firefly_stats().total_luminance,
+            film.firefly_stats().total_luminance
+        );
+
+        for j in 0..2 {
+            for i in 0..3 {
+                let (stats, loaded_stats) = (film.stats(i, j), loaded.stats(i, j));
+                let (aov, loaded_aov) = (film.aov(i, j), loaded.aov(i, j));
+
+                assert_eq!(loaded.pixel(i, j).e, film.pixel(i, j).e);
+                assert_eq!(
+                    (loaded_stats.count, loaded_stats.mean, loaded_stats.m2),
+                    (stats.count, stats.mean, stats.m2)
+                );
+                assert_eq!(loaded_aov.albedo.e, aov.albedo.e);
+                assert_eq!(loaded_aov.normal.e, aov.normal.e);
+                assert_eq!(loaded_aov.position.e, aov.position.e);
+                assert_eq!(loaded_aov.depth, aov.depth);
+                assert_eq!(loaded_aov.uv, aov.uv);
+                assert_eq!(loaded_aov.object_id, aov.object_id);
+                assert_eq!(loaded_aov.material_id, aov.material_id);
+                assert_eq!(loaded_aov.hit, aov.hit);
+            }
+        }
+    }
+
+    #[test]
+    fn damaged_checkpoints_are_rejected() {
+        let filename = temp_filename("film_test_damaged.checkpoint");
+        Film::new(4, 4, Filter::Box(0.5))
+            .save_checkpoint(&filename, &SETTINGS)
+            .unwrap();
+        let bytes = fs::read(&filename).unwrap();
+
+        // Cut off
+        fs::write(&filename, &bytes[..bytes.len() - 10]).unwrap();
+        assert!(Film::load_checkpoint(&filename, Filter::Box(0.5), &SETTINGS).is_err());
+
+        // Negative and overflowing sizes
+        for (width, height) in [(-4, 4), (0, 4), (i32::MAX, 4i32)] {
+            let mut damaged = bytes.clone();
+            damaged[12..16].copy_from_slice(&width.to_le_bytes());
+            damaged[16..20].copy_from_slice(&height.to_le_bytes());
+            fs::write(&filename, &damaged).unwrap();
+
+            assert!(Film::load_checkpoint(&filename, Filter::Box(0.5), &SETTINGS).is_err());
+        }
+
+        fs::remove_file(&filename).unwrap();
+    }
+
+    #[test]
+    fn checkpoints_from_other_renders_are_rejected() {
+        let filename = temp_filename("film_test_other.checkpoint");
+        let filter = Filter::Mitchell {
+            radius: 2.0,
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        };
+        Film::new(4, 4, filter)
+            .save_checkpoint(&filename, &SETTINGS)
+            .unwrap();
+
+        assert!(Film::load_checkpoint(&filename, filter, &SETTINGS).is_ok());
+
+        let other_filters = [
+            Filter::Box(2.0),
+            Filter::Mitchell {
+                radius: 2.0,
+                b: 1.0,
+                c: 0.0,
+            },
+        ];
+        for other in other_filters {
+            assert!(Film::load_checkpoint(&filename, other, &SETTINGS).is_err());
+        }
+
+        let other_settings = [
+            CheckpointSettings {
+                x_offset: 0,
+                ..SETTINGS
+            },
+            CheckpointSettings {
+                y_offset: 0,
+                ..SETTINGS
+            },
+            CheckpointSettings {
+                seed: 8,
+                ..SETTINGS
+            },
+        ];
+        for other in &other_settings {
+            assert!(Film::load_checkpoint(&filename, filter, other).is_err());
+        }
+
+        fs::remove_file(&filename).unwrap();
+    }
+}
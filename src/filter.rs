@@ -0,0 +1,94 @@
+use crate::rtweekend::PI;
+
+// Pixel reconstruction filter. Every sample is spread over the pixels whose centers are within
+// 'radius' of it (in pixels), weighted by the filter, and each pixel is the weighted average of
+// what landed on it. Wider filters blur a bit but get rid of the jaggies and ringing a box leaves
+// on high contrast edges. All of them are separable, the weight is filter(x) * filter(y)
+#[derive(Clone, Copy)]
+pub enum Filter {
+    // Every sample counts only for the pixel it's in, like before with a 0.5 radius
+    Box(f32),
+    // Weight falls linearly to 0 at the radius
+    Tent(f32),
+    // Gaussian of the given falloff, shifted so it reaches 0 at the radius
+    Gaussian { radius: f32, alpha: f32 },
+    // Mitchell-Netravali cubic. b = c = 1/3 is their recommended balance between blurring and
+    // ringing. Has slightly negative lobes that sharpen edges
+    Mitchell { radius: f32, b: f32, c: f32 },
+    // Blackman-Harris window, close to a Gaussian but with less blur for the same radius
+    BlackmanHarris(f32),
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter::Box(0.5)
+    }
+}
+
+impl Filter {
+    pub fn radius(&self) -> f32 {
+        match *self {
+            Filter::Box(radius) => radius,
+            Filter::Tent(radius) => radius,
+            Filter::Gaussian { radius, .. } => radius,
+            Filter::Mitchell { radius, .. } => radius,
+            Filter::BlackmanHarris(radius) => radius,
+        }
+    }
+
+    // The kind of filter and its parameters as plain numbers, which is how checkpoints record it
+    pub fn encode(&self) -> (u32, [f32; 3]) {
+        match *self {
+            Filter::Box(radius) => (0, [radius, 0.0, 0.0]),
+            Filter::Tent(radius) => (1, [radius, 0.0, 0.0]),
+            Filter::Gaussian { radius, alpha } => (2, [radius, alpha, 0.0]),
+            Filter::Mitchell { radius, b, c } => (3, [radius, b, c]),
+            Filter::BlackmanHarris(radius) => (4, [radius, 0.0, 0.0]),
+        }
+    }
+
+    // Weight of a sample (x, y) pixels away from a pixel's center
+    pub fn evaluate(&self, x: f32, y: f32) -> f32 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let radius = self.radius();
+        let x = x.abs();
+
+        if x > radius {
+            return 0.0;
+        }
+
+        match *self {
+            Filter::Box(_) => 1.0,
+            Filter::Tent(_) => radius - x,
+            Filter::Gaussian { alpha, .. } => {
+                ((-alpha * x * x).exp() - (-alpha * radius * radius).exp()).max(0.0)
+            }
+            Filter::Mitchell { b, c, .. } => {
+                // The cubic is defined from -2 to 2
+                let x = 2.0 * x / radius;
+
+                if x > 1.0 {
+                    ((-b - 6.0 * c) * x * x * x
+                        + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c))
+                        / 6.0
+                } else {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                        + (6.0 - 2.0 * b))
+                        / 6.0
+                }
+            }
+            Filter::BlackmanHarris(_) => {
+                // The window goes from 0 at -radius to 1 in the middle and back to 0 at radius
+                let t = 2.0 * PI * (x + radius) / (2.0 * radius);
+
+                0.35875 - 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() - 0.01168 * (3.0 * t).cos()
+            }
+        }
+    }
+}
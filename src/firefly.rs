@@ -0,0 +1,91 @@
+use crate::adaptive::PixelStats;
+use crate::Color;
+
+// Keeps fireflies (the odd sample that finds a bright light through a path that's very unlikely
+// to be taken, and leaves a white dot that takes forever to average out) out of the film. Both
+// ways darken the image a little, FireflyStats keeps track of by how much
+#[derive(Clone, Copy, Default)]
+pub struct FireflySuppression {
+    // Samples brighter than this luminance are scaled down to it, keeping their color
+    pub max_luminance: Option<f32>,
+    // Samples more than this many standard deviations above the mean of their pixel's samples
+    // so far are scaled down to that bound. Only once the pixel has outlier_min_samples, before
+    // that there's no telling what's an outlier. Works best when the lighting is mostly found by
+    // common paths, in scenes lit only by rare hits on a small light it throws a lot away
+    pub outlier_sigma: Option<f32>,
+    pub outlier_min_samples: i32,
+}
+
+impl FireflySuppression {
+    // The sample as it should go into the film, 'stats' being the pixel's samples so far
+    pub fn apply(
+        &self,
+        color: Color,
+        stats: &PixelStats,
+        firefly_stats: &mut FireflyStats,
+    ) -> Color {
+        let luminance = color.luminance();
+        let mut bound = f32::INFINITY;
+        let mut clamped = false;
+        let mut rejected = false;
+
+        if let Some(max_luminance) = self.max_luminance {
+            if luminance > max_luminance {
+                bound = max_luminance;
+                clamped = true;
+            }
+        }
+        if let Some(sigma) = self.outlier_sigma {
+            // A pixel whose samples so far were all the same (usually black) says nothing about
+            // what an outlier is
+            if stats.count() >= self.outlier_min_samples.max(2) && stats.deviation() > 0.0 {
+                let outlier_bound = stats.mean + sigma * stats.deviation();
+
+                if luminance > outlier_bound && outlier_bound < bound {
+                    bound = outlier_bound;
+                    clamped = false;
+                    rejected = true;
+                }
+            }
+        }
+
+        firefly_stats.samples += 1;
+        firefly_stats.total_luminance += luminance.max(0.0) as f64;
+
+        if !clamped && !rejected {
+            return color;
+        }
+
+        if clamped {
+            firefly_stats.clamped += 1;
+        } else {
+            firefly_stats.rejected += 1;
+        }
+        firefly_stats.removed_luminance += (luminance - bound) as f64;
+
+        color * (bound / luminance)
+    }
+}
+
+// How many samples were touched and how much light went with them
+#[derive(Clone, Copy, Default)]
+pub struct FireflyStats {
+    pub samples: u64,
+    // Over max_luminance
+    pub clamped: u64,
+    // Outliers
+    pub rejected: u64,
+    pub total_luminance: f64,
+    pub removed_luminance: f64,
+}
+
+impl FireflyStats {
+    // Fraction of the light in the samples that was taken out, i.e. how much darker the image is
+    pub fn bias(&self) -> f64 {
+        if self.total_luminance <= 0.0 {
+            return 0.0;
+        }
+
+        self.removed_luminance / self.total_luminance
+    }
+}
@@ -1,9 +1,15 @@
 use crate::aabb::AABB;
 use crate::material::Material;
-use crate::rtweekend::PI;
+use crate::rtweekend::{short_type_name, IdCounter, PI};
 use crate::{Point3, Ray, Vec3};
 use std::rc::Rc;
 
+// Every object gets an ID when it's created (0 means nothing was hit)
+pub fn next_object_id() -> u32 {
+    static OBJECT_IDS: IdCounter = IdCounter::new();
+    OBJECT_IDS.next()
+}
+
 #[derive(Clone)]
 pub struct HitRecord {
     pub point: Point3,
@@ -13,6 +19,9 @@ pub struct HitRecord {
     pub v: f32,
     pub front_face: bool,
     pub material_ptr: Option<Rc<dyn Material>>,
+    // Name of the object that was hit, for debugging
+    pub object: &'static str,
+    pub object_id: u32,
 }
 
 impl HitRecord {
@@ -25,6 +34,8 @@ impl HitRecord {
             v: 0.0,
             front_face: false,
             material_ptr: None,
+            object: "",
+            object_id: 0,
         }
     }
 
@@ -41,12 +52,17 @@ impl HitRecord {
 pub trait Hittable {
     fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, hit_record: &mut HitRecord) -> bool;
     fn bounding_box(&self, time0: f32, time1: f32, output_box: &mut AABB) -> bool;
+
+    fn name(&self) -> &'static str {
+        short_type_name::<Self>()
+    }
 }
 
 pub struct Sphere {
     center: Point3,
     radius: f32,
     material_ptr: Rc<dyn Material>,
+    id: u32,
 }
 
 impl Sphere {
@@ -55,6 +71,7 @@ impl Sphere {
             center,
             radius,
             material_ptr,
+            id: next_object_id(),
         }
     }
 
@@ -102,6 +119,8 @@ impl Hittable for Sphere {
         hit_record.set_face_normal(ray, &outward_normal);
         Sphere::get_sphere_uv(&outward_normal, &mut hit_record.u, &mut hit_record.v);
         hit_record.material_ptr = Some(self.material_ptr.clone());
+        hit_record.object = self.name();
+        hit_record.object_id = self.id;
 
         return true;
     }
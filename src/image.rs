@@ -0,0 +1,86 @@
+use crate::rtweekend::clamp;
+use crate::Color;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+
+// A plain grid of colors, indexed like the film ('j' goes bottom to top). What comes out of the
+// film and goes through post-processing before being written
+#[derive(Clone)]
+pub struct Image {
+    width: i32,
+    height: i32,
+    pixels: Vec<Color>,
+}
+
+impl Image {
+    pub fn new(width: i32, height: i32) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![Color::new(None, None, None); (width * height) as usize],
+        }
+    }
+
+    // Fills the image with whatever 'color' gives for each pixel
+    pub fn from_fn(width: i32, height: i32, color: &dyn Fn(i32, i32) -> Color) -> Image {
+        let mut image = Image::new(width, height);
+
+        for j in 0..height {
+            for i in 0..width {
+                image.set(i, j, color(i, j));
+            }
+        }
+
+        image
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    pub fn get(&self, i: i32, j: i32) -> Color {
+        self.pixels[(j * self.width + i) as usize]
+    }
+
+    pub fn set(&mut self, i: i32, j: i32, color: Color) {
+        self.pixels[(j * self.width + i) as usize] = color;
+    }
+
+    // Writes the image as a PPM. P3 means the colors are in ASCII format, width and height
+    // represent columns and rows and 255 is the max value. Colors are written as they are, from 0
+    // to 1, so they have to be display ready
+    pub fn write_ppm(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "P3\n{} {}\n255", self.width, self.height)?;
+
+        for j in (0..self.height).rev() {
+            for i in 0..self.width {
+                let color = self.get(i, j);
+
+                writeln!(
+                    out,
+                    "{} {} {}",
+                    (256.0 * clamp(color.x(), 0.0, 0.999)) as i32,
+                    (256.0 * clamp(color.y(), 0.0, 0.999)) as i32,
+                    (256.0 * clamp(color.z(), 0.0, 0.999)) as i32
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn save_ppm(&self, filename: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(filename)?);
+        self.write_ppm(&mut out)?;
+        out.flush()
+    }
+
+    // A new image with 'f' applied to every pixel
+    pub fn map(&self, f: &dyn Fn(Color) -> Color) -> Image {
+        Image::from_fn(self.width(), self.height(), &|i, j| f(self.get(i, j)))
+    }
+}
@@ -0,0 +1,467 @@
+use crate::rtweekend::INFINITY;
+use crate::sampler::{radical_inverse, Sampler};
+use crate::{Ray, Vec3};
+use std::fs;
+
+// A single refracting surface of a lens prescription. Everything is in millimeters. The
+// curvature radius is positive when the center of the sphere is towards the film, and a radius
+// of 0 marks the aperture stop. 'thickness' is the distance to the next surface (or the film for
+// the last one) and 'ior' is the index of refraction of the glass right after this surface, with
+// 0 or 1 meaning air
+pub struct LensElement {
+    pub curvature_radius: f32,
+    pub thickness: f32,
+    pub ior: f32,
+    pub aperture_radius: f32,
+}
+
+impl LensElement {
+    fn is_stop(&self) -> bool {
+        self.curvature_radius == 0.0
+    }
+
+    fn ior(&self) -> f32 {
+        if self.ior == 0.0 {
+            1.0
+        } else {
+            self.ior
+        }
+    }
+}
+
+// A stack of spherical elements listed from the scene side to the film side. Lens space has the
+// film at z = 0 and the lens extending towards -z, which is where the scene is
+pub struct LensSystem {
+    elements: Vec<LensElement>,
+}
+
+impl LensSystem {
+    pub fn new(elements: Vec<LensElement>) -> LensSystem {
+        LensSystem { elements }
+    }
+
+    // Reads a prescription in the usual text format: one surface per line with curvature radius,
+    // thickness, index of refraction and aperture diameter, all in millimeters. Empty lines and
+    // lines starting with '#' are ignored
+    pub fn load(filename: &str) -> Result<LensSystem, String> {
+        let contents = fs::read_to_string(filename)
+            .map_err(|e| format!("Could not read lens file {}: {}", filename, e))?;
+        let mut elements = vec![];
+
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let values = line
+                .split_whitespace()
+                .map(|value| value.parse::<f32>())
+                .collect::<Result<Vec<f32>, _>>()
+                .map_err(|e| format!("{}:{}: {}", filename, number + 1, e))?;
+
+            if values.len() != 4 {
+                return Err(format!(
+                    "{}:{}: expected 4 values, found {}",
+                    filename,
+                    number + 1,
+                    values.len()
+                ));
+            }
+
+            elements.push(LensElement {
+                curvature_radius: values[0],
+                thickness: values[1],
+                ior: values[2],
+                aperture_radius: values[3] / 2.0,
+            });
+        }
+
+        if elements.is_empty() {
+            return Err(format!("{} doesn't contain any lens elements", filename));
+        }
+
+        Ok(LensSystem::new(elements))
+    }
+
+    // 50mm f/2 double Gauss (US patent 2,673,491, scaled from the 100mm design in Modern Lens
+    // Design), a classic normal lens
+    pub fn double_gauss_50mm() -> LensSystem {
+        let prescription = [
+            [29.475, 3.76, 1.67, 25.2],
+            [84.83, 0.12, 1.0, 25.2],
+            [19.275, 4.025, 1.67, 23.0],
+            [40.77, 3.275, 1.699, 23.0],
+            [12.75, 5.705, 1.0, 18.0],
+            [0.0, 4.5, 0.0, 17.1],
+            [-14.495, 1.18, 1.603, 17.0],
+            [40.77, 6.065, 1.658, 20.0],
+            [-20.385, 0.19, 1.0, 20.0],
+            [437.065, 3.22, 1.717, 20.0],
+            [-39.73, 0.0, 1.0, 20.0],
+        ];
+
+        LensSystem::new(
+            prescription
+                .iter()
+                .map(|e| LensElement {
+                    curvature_radius: e[0],
+                    thickness: e[1],
+                    ior: e[2],
+                    aperture_radius: e[3] / 2.0,
+                })
+                .collect(),
+        )
+    }
+
+    // Distance from the film to the front element
+    pub fn front_z(&self) -> f32 {
+        self.elements.iter().map(|e| e.thickness).sum()
+    }
+
+    // Distance from the film to the rear element
+    pub fn rear_z(&self) -> f32 {
+        self.elements.last().unwrap().thickness
+    }
+
+    fn rear_aperture(&self) -> f32 {
+        self.elements.last().unwrap().aperture_radius
+    }
+
+    // Follows a lens space ray leaving the film through every surface. Returns the ray coming out
+    // of the front element, or None if it gets blocked or totally internally reflected
+    pub fn trace_from_film(&self, ray: &Ray) -> Option<Ray> {
+        let mut element_z = 0.0;
+        let mut origin = ray.origin();
+        let mut direction = ray.direction();
+
+        for i in (0..self.elements.len()).rev() {
+            let element = &self.elements[i];
+            element_z -= element.thickness;
+
+            let (t, normal) = if element.is_stop() {
+                if direction.z() >= 0.0 {
+                    return None;
+                }
+                ((element_z - origin.z()) / direction.z(), None)
+            } else {
+                let radius = element.curvature_radius;
+                let (t, normal) =
+                    Self::intersect_element(radius, element_z + radius, &origin, &direction)?;
+                (t, Some(normal))
+            };
+
+            let hit = origin + direction * t;
+
+            if hit.x() * hit.x() + hit.y() * hit.y()
+                > element.aperture_radius * element.aperture_radius
+            {
+                return None;
+            }
+
+            origin = hit;
+
+            if let Some(normal) = normal {
+                let eta_i = element.ior();
+                let eta_t = if i > 0 {
+                    self.elements[i - 1].ior()
+                } else {
+                    1.0
+                };
+                direction = Self::refract(&Vec3::unit_vector(-direction), &normal, eta_i / eta_t)?;
+            }
+        }
+
+        Some(Ray::new(Some(origin), Some(direction), None))
+    }
+
+    // Same as above but for a ray coming from the scene towards the film
+    pub fn trace_from_scene(&self, ray: &Ray) -> Option<Ray> {
+        let mut element_z = -self.front_z();
+        let mut origin = ray.origin();
+        let mut direction = ray.direction();
+
+        for i in 0..self.elements.len() {
+            let element = &self.elements[i];
+
+            let (t, normal) = if element.is_stop() {
+                if direction.z() <= 0.0 {
+                    return None;
+                }
+                ((element_z - origin.z()) / direction.z(), None)
+            } else {
+                let radius = element.curvature_radius;
+                let (t, normal) =
+                    Self::intersect_element(radius, element_z + radius, &origin, &direction)?;
+                (t, Some(normal))
+            };
+
+            let hit = origin + direction * t;
+
+            if hit.x() * hit.x() + hit.y() * hit.y()
+                > element.aperture_radius * element.aperture_radius
+            {
+                return None;
+            }
+
+            origin = hit;
+
+            if let Some(normal) = normal {
+                let eta_i = if i > 0 {
+                    self.elements[i - 1].ior()
+                } else {
+                    1.0
+                };
+                let eta_t = element.ior();
+                direction = Self::refract(&Vec3::unit_vector(-direction), &normal, eta_i / eta_t)?;
+            }
+
+            element_z += element.thickness;
+        }
+
+        Some(Ray::new(Some(origin), Some(direction), None))
+    }
+
+    fn intersect_element(
+        radius: f32,
+        z_center: f32,
+        origin: &Vec3,
+        direction: &Vec3,
+    ) -> Option<(f32, Vec3)> {
+        let oc = *origin - Vec3::new(None, None, Some(z_center));
+        let a = direction.len_squared();
+        let b = 2.0 * direction.dot(&oc);
+        let c = oc.len_squared() - radius * radius;
+        let discriminant = b * b - 4.0 * a * c;
+
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        // Numerically stable form of the quadratic formula
+        let q = if b < 0.0 {
+            -0.5 * (b - discriminant.sqrt())
+        } else {
+            -0.5 * (b + discriminant.sqrt())
+        };
+        let t0 = f32::min(q / a, c / q);
+        let t1 = f32::max(q / a, c / q);
+        // A sphere is hit twice, the element is the part of it facing the incoming ray
+        let use_closer = (direction.z() > 0.0) ^ (radius < 0.0);
+        let t = if use_closer { t0 } else { t1 };
+
+        if t < 0.0 {
+            return None;
+        }
+
+        let mut normal = Vec3::unit_vector(oc + *direction * t);
+
+        if normal.dot(direction) > 0.0 {
+            normal = -normal;
+        }
+
+        Some((t, normal))
+    }
+
+    // 'incident' points away from the surface, on the same side as 'normal'. Returns None on
+    // total internal reflection
+    fn refract(incident: &Vec3, normal: &Vec3, eta: f32) -> Option<Vec3> {
+        let cos_i = normal.dot(incident);
+        let sin2_i = f32::max(0.0, 1.0 - cos_i * cos_i);
+        let sin2_t = eta * eta * sin2_i;
+
+        if sin2_t >= 1.0 {
+            return None;
+        }
+
+        let cos_t = (1.0 - sin2_t).sqrt();
+
+        Some(-*incident * eta + *normal * (eta * cos_i - cos_t))
+    }
+
+    // Principal plane and focal point along z, found from a ray parallel to the axis and what
+    // comes out of the lens
+    fn cardinal_points(ray_in: &Ray, ray_out: &Ray) -> (f32, f32) {
+        let tf = -ray_out.origin().x() / ray_out.direction().x();
+        let tp = (ray_in.origin().x() - ray_out.origin().x()) / ray_out.direction().x();
+
+        (ray_out.at(tp).z(), ray_out.at(tf).z())
+    }
+
+    // Approximates the whole system as a thick lens by tracing a ray close to the axis through it
+    // in both directions. Returns the principal planes and focal points for the scene and film
+    // sides
+    fn thick_lens(&self) -> Option<([f32; 2], [f32; 2])> {
+        let x = 0.01 * self.rear_aperture();
+
+        let scene_ray = Ray::new(
+            Some(Vec3::new(Some(x), None, Some(-self.front_z() - 1.0))),
+            Some(Vec3::new(None, None, Some(1.0))),
+            None,
+        );
+        let (pz0, fz0) = Self::cardinal_points(&scene_ray, &self.trace_from_scene(&scene_ray)?);
+
+        let film_ray = Ray::new(
+            Some(Vec3::new(Some(x), None, Some(-self.rear_z() + 1.0))),
+            Some(Vec3::new(None, None, Some(-1.0))),
+            None,
+        );
+        let (pz1, fz1) = Self::cardinal_points(&film_ray, &self.trace_from_film(&film_ray)?);
+
+        Some(([pz0, pz1], [fz0, fz1]))
+    }
+
+    // Effective focal length in millimeters
+    pub fn focal_length(&self) -> Option<f32> {
+        let (pz, fz) = self.thick_lens()?;
+
+        Some(fz[0] - pz[0])
+    }
+
+    // Moves the film so objects 'distance' millimeters away from it are in focus
+    pub fn focus(&mut self, distance: f32) {
+        let (pz, fz) = match self.thick_lens() {
+            Some(cardinal_points) => cardinal_points,
+            None => {
+                eprintln!("Lens: paraxial rays don't make it through, can't focus");
+                return;
+            }
+        };
+        let f = fz[0] - pz[0];
+        let z = -distance;
+        let c = (pz[1] - z - pz[0]) * (pz[1] - z - 4.0 * f - pz[0]);
+
+        if c <= 0.0 {
+            eprintln!("Lens: can't focus at {}mm, it's too close", distance);
+            return;
+        }
+
+        let delta = 0.5 * (pz[1] - z + pz[0] - c.sqrt());
+        self.elements.last_mut().unwrap().thickness += delta;
+    }
+
+    // Stops the lens down to roughly 'f_number' by shrinking the aperture stop. It can't be
+    // opened up past what the prescription allows
+    pub fn set_f_number(&mut self, f_number: f32) {
+        let focal_length = match self.focal_length() {
+            Some(focal_length) => focal_length,
+            None => return,
+        };
+
+        if let Some(stop) = self.elements.iter_mut().find(|e| e.is_stop()) {
+            stop.aperture_radius = f32::min(stop.aperture_radius, focal_length / f_number / 2.0);
+        }
+    }
+
+    // Bounds of the exit pupil (the part of the rear element that light from the film can
+    // actually get through) for rings of the film, so rays can be aimed at it instead of the
+    // whole rear element, most of which is usually blocked by the stop
+    pub fn exit_pupil(&self, film_diagonal: f32) -> ExitPupil {
+        let intervals = 32;
+        let film_radius = film_diagonal / 2.0;
+
+        ExitPupil {
+            bounds: (0..intervals)
+                .map(|i| {
+                    let r0 = i as f32 / intervals as f32 * film_radius;
+                    let r1 = (i + 1) as f32 / intervals as f32 * film_radius;
+                    self.bound_exit_pupil(r0, r1)
+                })
+                .collect(),
+            film_radius,
+            rear_z: self.rear_z(),
+        }
+    }
+
+    fn bound_exit_pupil(&self, r0: f32, r1: f32) -> [f32; 4] {
+        let samples = 16384;
+        let rear_radius = 1.5 * self.rear_aperture();
+        let mut bounds = [INFINITY, INFINITY, -INFINITY, -INFINITY];
+
+        for i in 0..samples {
+            // Deterministic, well spread points over the film ring and the rear element
+            let film_x = r0 + (r1 - r0) * (i as f32 + 0.5) / samples as f32;
+            let rear_x = -rear_radius + 2.0 * rear_radius * radical_inverse(2, i);
+            let rear_y = -rear_radius + 2.0 * rear_radius * radical_inverse(3, i);
+
+            if rear_x >= bounds[0]
+                && rear_x <= bounds[2]
+                && rear_y >= bounds[1]
+                && rear_y <= bounds[3]
+            {
+                continue;
+            }
+
+            let film_point = Vec3::new(Some(film_x), None, None);
+            let rear_point = Vec3::new(Some(rear_x), Some(rear_y), Some(-self.rear_z()));
+            let ray = Ray::new(Some(film_point), Some(rear_point - film_point), None);
+
+            if self.trace_from_film(&ray).is_some() {
+                bounds[0] = f32::min(bounds[0], rear_x);
+                bounds[1] = f32::min(bounds[1], rear_y);
+                bounds[2] = f32::max(bounds[2], rear_x);
+                bounds[3] = f32::max(bounds[3], rear_y);
+            }
+        }
+
+        // Nothing got through, aim at the whole rear element
+        if bounds[0] > bounds[2] {
+            return [-rear_radius, -rear_radius, rear_radius, rear_radius];
+        }
+
+        // Grow the bounds a bit so thin slivers between the sample points aren't missed
+        let margin = 2.0 * 2.0 * rear_radius / (samples as f32).sqrt();
+        [
+            bounds[0] - margin,
+            bounds[1] - margin,
+            bounds[2] + margin,
+            bounds[3] + margin,
+        ]
+    }
+}
+
+pub struct ExitPupil {
+    bounds: Vec<[f32; 4]>,
+    film_radius: f32,
+    rear_z: f32,
+}
+
+impl ExitPupil {
+    // Point on the rear element plane to aim at from 'film_point'. The bounds were computed along
+    // the x axis, so they get rotated to the film point's angle
+    pub fn sample(&self, film_point: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let r = (film_point.x() * film_point.x() + film_point.y() * film_point.y()).sqrt();
+        let b = self.bounds_at(r);
+        let (u1, u2) = sampler.get_2d();
+        let x = b[0] + (b[2] - b[0]) * u1;
+        let y = b[1] + (b[3] - b[1]) * u2;
+        let (sin, cos) = if r > 0.0 {
+            (film_point.y() / r, film_point.x() / r)
+        } else {
+            (0.0, 1.0)
+        };
+
+        Vec3::new(
+            Some(cos * x - sin * y),
+            Some(sin * x + cos * y),
+            Some(-self.rear_z),
+        )
+    }
+
+    // Area of the bounds sample() aims at from 'film_point', in square millimeters
+    pub fn area(&self, film_point: &Vec3) -> f32 {
+        let r = (film_point.x() * film_point.x() + film_point.y() * film_point.y()).sqrt();
+        let b = self.bounds_at(r);
+
+        (b[2] - b[0]) * (b[3] - b[1])
+    }
+
+    // Bounds for film points 'r' away from the center
+    fn bounds_at(&self, r: f32) -> [f32; 4] {
+        let interval =
+            ((r / self.film_radius * self.bounds.len() as f32) as usize).min(self.bounds.len() - 1);
+
+        self.bounds[interval]
+    }
+}
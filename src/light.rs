@@ -0,0 +1,235 @@
+use crate::rtweekend::{degrees_to_radians, INFINITY, PI};
+use crate::{Color, Point3, Vec3};
+
+// A direction towards a light picked by Light::sample()
+pub struct LightSample {
+    // Unit vector from the point being lit towards the light
+    pub direction: Vec3,
+    // Light arriving along it
+    pub radiance: Color,
+    // How far the shadow ray has to go, INFINITY for lights that are infinitely far away
+    pub distance: f32,
+    // Per solid angle. None for delta lights, a single point or direction that rays can't run
+    // into, so sampling is the only way to find them
+    pub pdf: Option<f32>,
+}
+
+// Light that doesn't come from emissive geometry, sampled directly at every diffuse bounce
+pub trait Light {
+    // A direction to the light as seen from 'point', None if it can't reach it
+    fn sample(&self, point: &Point3, u: (f32, f32)) -> Option<LightSample>;
+
+    // Radiance seen by a ray that leaves the scene towards 'direction', for lights that are far
+    // away and big enough to be run into
+    fn radiance(&self, _direction: &Vec3) -> Color {
+        Color::new(None, None, None)
+    }
+
+    // Pdf of sample() picking 'direction'
+    fn pdf(&self, _direction: &Vec3) -> f32 {
+        0.0
+    }
+}
+
+// The sun, or any other distant light that shows up as a disk in the sky. 'direction' points
+// towards it and 'angular_diameter' is in degrees (the real sun is about 0.53). The size is what
+// gives shadows their soft edge
+pub struct SunLight {
+    direction: Vec3,
+    radiance: Color,
+    // 1 - cos of the disk's angular radius. Tiny for the real sun, so it's kept as is rather than
+    // as the cosine, which would round off most of it
+    one_minus_cos_max: f32,
+}
+
+impl SunLight {
+    pub fn new(direction: Vec3, radiance: Color, angular_diameter: Option<f32>) -> SunLight {
+        let angular_diameter = angular_diameter.unwrap_or(0.53);
+
+        SunLight {
+            direction: Vec3::unit_vector(direction),
+            radiance,
+            one_minus_cos_max: 2.0 * degrees_to_radians(angular_diameter / 4.0).sin().powi(2),
+        }
+    }
+
+    fn covers(&self, direction: &Vec3) -> bool {
+        1.0 - Vec3::unit_vector(*direction).dot(&self.direction) <= self.one_minus_cos_max
+    }
+}
+
+impl Light for SunLight {
+    // Uniform over the cone the disk covers
+    fn sample(&self, _point: &Point3, u: (f32, f32)) -> Option<LightSample> {
+        let cos_theta = 1.0 - u.0 * self.one_minus_cos_max;
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.1;
+        let (a, b) = perpendicular(&self.direction);
+        let direction =
+            self.direction * cos_theta + a * (sin_theta * phi.cos()) + b * (sin_theta * phi.sin());
+
+        Some(LightSample {
+            direction,
+            radiance: self.radiance,
+            distance: INFINITY,
+            pdf: Some(1.0 / (2.0 * PI * self.one_minus_cos_max)),
+        })
+    }
+
+    fn radiance(&self, direction: &Vec3) -> Color {
+        if self.covers(direction) {
+            self.radiance
+        } else {
+            Color::new(None, None, None)
+        }
+    }
+
+    fn pdf(&self, direction: &Vec3) -> f32 {
+        if self.covers(direction) {
+            1.0 / (2.0 * PI * self.one_minus_cos_max)
+        } else {
+            0.0
+        }
+    }
+}
+
+// Light from a single point, shining equally in every direction. 'intensity' is per steradian, the
+// light falling on a surface drops off with the square of its distance
+pub struct PointLight {
+    position: Point3,
+    intensity: Color,
+}
+
+impl PointLight {
+    pub fn new(position: Point3, intensity: Color) -> PointLight {
+        PointLight {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, point: &Point3, _u: (f32, f32)) -> Option<LightSample> {
+        delta_sample(point, &self.position, self.intensity)
+    }
+}
+
+// A point light shining only into a cone around the direction towards 'look_at'. 'angle' is the
+// cone's full width in degrees, 45 by default, and the light fades out smoothly over the outer
+// 'blend' fraction of it, 0.15 by default
+pub struct SpotLight {
+    position: Point3,
+    direction: Vec3,
+    intensity: Color,
+    // Cosines of the angle from the axis where the fading starts and where it's dark
+    cos_inner: f32,
+    cos_outer: f32,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Point3,
+        look_at: Point3,
+        intensity: Color,
+        angle: Option<f32>,
+        blend: Option<f32>,
+    ) -> SpotLight {
+        let half_angle = degrees_to_radians(angle.unwrap_or(45.0).clamp(0.0, 360.0) / 2.0);
+        let blend = blend.unwrap_or(0.15).clamp(0.0, 1.0);
+
+        SpotLight {
+            position,
+            direction: Vec3::unit_vector(look_at - position),
+            intensity,
+            cos_inner: (half_angle * (1.0 - blend)).cos(),
+            cos_outer: half_angle.cos(),
+        }
+    }
+
+    // How much of the intensity goes out along 'direction' (a unit vector away from the light)
+    fn falloff(&self, direction: &Vec3) -> f32 {
+        let cos_theta = direction.dot(&self.direction);
+
+        if cos_theta >= self.cos_inner {
+            return 1.0;
+        }
+        if cos_theta <= self.cos_outer {
+            return 0.0;
+        }
+
+        let t = (cos_theta - self.cos_outer) / (self.cos_inner - self.cos_outer);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, point: &Point3, _u: (f32, f32)) -> Option<LightSample> {
+        let falloff = self.falloff(&Vec3::unit_vector(*point - self.position));
+
+        if falloff <= 0.0 {
+            return None;
+        }
+
+        delta_sample(point, &self.position, self.intensity * falloff)
+    }
+}
+
+// Light arriving from a single direction with no falloff, like sunlight with hard shadows.
+// 'direction' points towards the light and 'irradiance' is what a surface facing it receives
+pub struct DirectionalLight {
+    direction: Vec3,
+    irradiance: Color,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Color) -> DirectionalLight {
+        DirectionalLight {
+            direction: Vec3::unit_vector(direction),
+            irradiance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _point: &Point3, _u: (f32, f32)) -> Option<LightSample> {
+        Some(LightSample {
+            direction: self.direction,
+            radiance: self.irradiance,
+            distance: INFINITY,
+            pdf: None,
+        })
+    }
+}
+
+// Light from 'position' reaching 'point', falling off with the square of the distance
+fn delta_sample(point: &Point3, position: &Point3, intensity: Color) -> Option<LightSample> {
+    let to_light = *position - *point;
+    let distance_squared = to_light.len_squared();
+
+    if distance_squared <= 0.0 {
+        return None;
+    }
+
+    let distance = distance_squared.sqrt();
+
+    Some(LightSample {
+        direction: to_light / distance,
+        radiance: intensity / distance_squared,
+        distance,
+        pdf: None,
+    })
+}
+
+// Two unit vectors perpendicular to 'n' and to each other
+fn perpendicular(n: &Vec3) -> (Vec3, Vec3) {
+    let helper = if n.x().abs() > 0.9 {
+        Vec3::new(None, Some(1.0), None)
+    } else {
+        Vec3::new(Some(1.0), None, None)
+    };
+    let a = Vec3::unit_vector(n.cross(&helper));
+    let b = n.cross(&a);
+
+    (a, b)
+}
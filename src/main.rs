@@ -1,27 +1,71 @@
 use crate::aarect::{XYRect, XZRect, YZRect};
-use crate::camera::Camera;
+use crate::adaptive::PixelStats;
+use crate::aov::AovSample;
+use crate::aperture::{Aperture, CircularAperture, ImageAperture, PolygonalAperture};
+use crate::background::{Background, ConstantBackground, GradientBackground, TextureBackground};
+use crate::camera::{
+    Camera, EquirectangularCamera, FisheyeCamera, Focus, OdsCamera, OrthographicCamera,
+    PerspectiveCamera, PhysicalSettings, ReadoutDirection, RealisticCamera, RollingShutter,
+    RollingShutterCamera,
+};
+use crate::color::{ColorSpace, TransferFunction, ViewTransform};
+use crate::denoise::Denoiser;
+use crate::environment::EnvironmentMap;
+use crate::film::{CheckpointSettings, Film};
+use crate::filter::Filter;
+use crate::firefly::FireflySuppression;
 use crate::hittable::{HitRecord, Hittable, HittableList, Sphere};
+use crate::image::Image;
+use crate::lens::LensSystem;
+use crate::light::{DirectionalLight, Light, PointLight, SpotLight};
 use crate::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
 use crate::moving_sphere::MovingSphere;
+use crate::postprocess::{Frame, PostEffect};
 use crate::ray::Ray;
-use crate::rtweekend::{clamp, random, INFINITY};
+use crate::rtweekend::{clamp, degrees_to_radians, random, seeded_rng, Rng, INFINITY};
+use crate::sampler::{HaltonSampler, IndependentSampler, Sampler, SobolSampler, StratifiedSampler};
+use crate::sky::PhysicalSky;
 use crate::texture::{CheckerTexture, ImageTexture, NoiseTexture};
+use crate::tonemap::ToneMapper;
 use crate::vec3::{Color, Point3, Vec3};
 use std::io;
 use std::io::Write;
+use std::process;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 mod aabb;
 mod aarect;
+mod adaptive;
+mod aov;
+mod aperture;
+mod background;
 mod bvh;
 mod camera;
+mod color;
+mod denoise;
+mod distribution;
+mod environment;
+mod exr;
+mod film;
+mod filter;
+mod firefly;
 mod hittable;
+mod image;
+mod lens;
+mod light;
 mod material;
 mod moving_sphere;
 mod perlin;
+mod postprocess;
 mod ray;
 mod rtweekend;
+mod sampler;
+mod sky;
 mod texture;
+mod tonemap;
 mod vec3;
 
 fn main() {
@@ -30,31 +74,52 @@ fn main() {
     let mut img_width = 400; //1200; //200;
     let mut samples_per_pixel = 100; //500;
     let max_depth = 50;
+    // Adaptive sampling. With a threshold, pixels stop taking samples once their estimated noise
+    // (as a fraction of full white, after gamma) drops below it, and samples_per_pixel becomes
+    // the maximum. Every pixel takes at least min_samples first. To render until the noise is low
+    // enough rather than to a fixed sample count, raise samples_per_pixel well above what's needed
+    let adaptive_threshold: Option<f32> = None; //Some(0.01);
+    let min_samples = 16;
+    // Writes an image of how many samples each pixel needed, handy to tune the threshold
+    let sample_heatmap: Option<&str> = None; //Some("samples.ppm");
+
+    // Same seed, same image. Change it to get a different noise pattern (and random scene)
+    let seed: u64 = 0;
+    // Colour space the rendering happens in. Colours written in the scenes are linear sRGB and,
+    // like textures, get converted to it. ACEScg mixes saturated colours more realistically
+    let working_space = match 0 {
+        1 => ColorSpace::AcesCg,
+        _ => ColorSpace::LinearSrgb,
+    };
+
     // World
+    let mut scene_rng = seeded_rng(seed, 0);
     let world: HittableList;
     let mut look_from = Point3::new(Some(13.0), Some(2.0), Some(3.0));
     let mut look_at = Point3::new(None, None, None);
     let mut v_fov: f32 = 20.0; //40.0;
     let mut aperture: f32 = 0.0;
+    let mut focus = Focus::LookAt;
     let mut background = Color::new(Some(0.70), Some(0.80), Some(1.0));
 
     match 0 {
         1 => {
-            world = random_scene();
+            world = random_scene(&mut scene_rng, working_space);
             aperture = 0.1;
+            focus = Focus::Distance(10.0);
         }
-        2 => world = two_spheres(),
-        3 => world = two_perlin_spheres(),
-        4 => world = earth(),
+        2 => world = two_spheres(working_space),
+        3 => world = two_perlin_spheres(&mut scene_rng),
+        4 => world = earth(working_space),
         5 => {
-            world = simple_light();
+            world = simple_light(&mut scene_rng, working_space);
             samples_per_pixel = 400;
             background = Color::new(None, None, None);
             look_from = Point3::new(Some(26.0), Some(3.0), Some(6.0));
             look_at = Point3::new(None, Some(2.0), None);
         }
         6 | _ => {
-            world = cornell_box();
+            world = cornell_box(working_space);
             aspect_ratio = 1.0;
             img_width = 600;
             samples_per_pixel = 200;
@@ -64,72 +129,721 @@ fn main() {
             v_fov = 40.0;
         }
     }
-    // Image 2
-    let img_height = (img_width as f32 / aspect_ratio) as i32;
+    // What rays that miss everything run into, which also lights the scene. 1 is the blue-white
+    // sky of the first book, 2 wraps a texture around the scene (an equirectangular panorama for
+    // an ImageTexture), anything else is the scene's constant color
+    let background: Rc<dyn Background> = match 0 {
+        1 => Rc::new(GradientBackground::new(
+            working_space.convert_linear_srgb(Color::new(Some(1.0), Some(1.0), Some(1.0))),
+            working_space.convert_linear_srgb(Color::new(Some(0.5), Some(0.7), Some(1.0))),
+        )),
+        2 => Rc::new(TextureBackground::new(Rc::new(ImageTexture::new(
+            "../../textures/panorama.jpg",
+            working_space,
+        )))),
+        _ => Rc::new(ConstantBackground::new(
+            working_space.convert_linear_srgb(background),
+        )),
+    };
+    // Image based lighting. An equirectangular map, best an HDR one, replaces the background and
+    // lights the scene. Rotation turns it around the vertical axis, in degrees
+    let environment_map: Option<&str> = None; //Some("../../textures/environment.hdr");
+    let environment_rotation: f32 = 0.0;
+    let environment_intensity: f32 = 1.0;
+    // Physical sky and sun for outdoor scenes, replacing the background (and environment map).
+    // The sun is elevation degrees above the horizon and azimuth degrees around from -Z towards
+    // +X. The sky is in real units, right for the physical camera, for the others turn
+    // sky_intensity down to something like 0.0001
+    let sky: Option<(f32, f32)> = None; //Some((30.0, 45.0));
+    let turbidity: f32 = 3.0;
+    let sky_intensity: f32 = 1.0;
+    let sun_diameter: f32 = 0.53;
+    let mut lights: Vec<Rc<dyn Light>> = Vec::new();
+    let background: Rc<dyn Background> = match (sky, environment_map) {
+        (Some((elevation, azimuth)), _) => {
+            let sky = PhysicalSky::new(
+                elevation,
+                azimuth,
+                Some(turbidity),
+                Some(sky_intensity),
+                None,
+                working_space,
+            );
+
+            if let Some(sun) = sky.sun(Some(sun_diameter)) {
+                lights.push(Rc::new(sun));
+            }
+            match EnvironmentMap::from_fn(1024, 512, &|direction| sky.radiance(direction)) {
+                Ok(environment) => Rc::new(environment),
+                Err(e) => {
+                    eprintln!("Could not bake the sky: {}", e);
+                    return;
+                }
+            }
+        }
+        (None, Some(filename)) => match EnvironmentMap::new(
+            filename,
+            working_space,
+            Some(environment_rotation),
+            Some(environment_intensity),
+        ) {
+            Ok(environment) => Rc::new(environment),
+            Err(e) => {
+                eprintln!("Could not load the environment map {}: {}", filename, e);
+                return;
+            }
+        },
+        (None, None) => background,
+    };
+    // What camera rays see where they miss everything, when it shouldn't be what lights the scene.
+    // 1 puts a black backdrop in front of it (say to composite an object lit by an HDR map),
+    // anything else shows the background
+    let camera_background: Rc<dyn Background> = match 0 {
+        1 => Rc::new(ConstantBackground::new(Color::new(None, None, None))),
+        _ => background.clone(),
+    };
+    // Lights that aren't geometry, like in other renderers: 1 is a point light, 2 a spot light
+    // (45 degrees wide, pointing down) and 3 a directional light through the open front. Point and
+    // spot intensities fall off with the square of the distance. Sized for the Cornell box
+    match 0 {
+        1 => lights.push(Rc::new(PointLight::new(
+            Point3::new(Some(278.0), Some(500.0), Some(278.0)),
+            working_space.convert_linear_srgb(Color::new(
+                Some(100000.0),
+                Some(100000.0),
+                Some(100000.0),
+            )),
+        ))),
+        2 => lights.push(Rc::new(SpotLight::new(
+            Point3::new(Some(278.0), Some(500.0), Some(278.0)),
+            Point3::new(Some(278.0), None, Some(278.0)),
+            working_space.convert_linear_srgb(Color::new(
+                Some(300000.0),
+                Some(300000.0),
+                Some(300000.0),
+            )),
+            Some(45.0),
+            None,
+        ))),
+        3 => lights.push(Rc::new(DirectionalLight::new(
+            Vec3::new(Some(0.3), Some(0.4), Some(-1.0)),
+            working_space.convert_linear_srgb(Color::new(Some(3.0), Some(3.0), Some(3.0))),
+        ))),
+        _ => {}
+    }
     // Camera
+    // Focus, 1 is on whatever the center of the image hits (found before rendering) and 2 on a
+    // point in the world (here the metal sphere of the random scene). Anything else keeps the
+    // scene's focus
+    match 0 {
+        1 => focus = Focus::Auto,
+        2 => focus = Focus::Point(Point3::new(Some(4.0), Some(1.0), None)),
+        _ => {}
+    }
     let v_up = Point3::new(None, Some(1.0), None);
-    let dist_to_focus = 10.0;
-    let cam = Camera::new(
-        look_from,
-        look_at,
-        v_up,
-        v_fov,
-        aspect_ratio,
-        aperture,
-        dist_to_focus,
-        None,
-        Some(1.0),
-    );
+    let dist_to_focus = (look_from - look_at).len();
+    // Tilt-shift for the perspective cameras. The shift slides the image sideways and up, as a
+    // fraction of the frame, and tilt and swing turn the plane of focus up and to the right, in
+    // degrees
+    let lens_shift: (f32, f32) = (0.0, 0.0); //(0.0, 0.2);
+    let lens_tilt: (f32, f32) = (0.0, 0.0); //(10.0, 0.0);
+
+    // Shape of the aperture, which is what out of focus highlights (bokeh) take. 1 is a diaphragm
+    // of 6 blades turned 15 degrees, 2 a mask image (white lets light through), anything else is
+    // round. Cat eye clips the bokeh towards the corners like a real lens barrel, 0 disables it
+    let aperture_shape: Rc<dyn Aperture> = match 0 {
+        1 => Rc::new(PolygonalAperture::new(6, Some(15.0))),
+        2 => Rc::new(ImageAperture::new("../../textures/aperture.png")),
+        _ => Rc::new(CircularAperture),
+    };
+    let cat_eye: f32 = 0.0; //0.5;
+    let configure = |cam: &mut PerspectiveCamera| {
+        cam.set_lens_shift(lens_shift.0, lens_shift.1);
+        cam.set_tilt(lens_tilt.0, lens_tilt.1);
+        cam.set_aperture_shape(aperture_shape.clone());
+        cam.set_cat_eye(cat_eye);
+        cam.set_focus(&focus, &world);
+    };
+    let cam: Rc<dyn Camera> = match 0 {
+        1 => {
+            // Frame the same area the perspective camera would see at the look_at distance
+            let viewport_height =
+                2.0 * (look_from - look_at).len() * (degrees_to_radians(v_fov) / 2.0).tan();
+            Rc::new(OrthographicCamera::new(
+                look_from,
+                look_at,
+                v_up,
+                viewport_height,
+                aspect_ratio,
+                None,
+                Some(1.0),
+            ))
+        }
+        2 => Rc::new(FisheyeCamera::new(
+            look_from,
+            look_at,
+            v_up,
+            180.0,
+            aspect_ratio,
+            None,
+            Some(1.0),
+        )),
+        3 => {
+            aspect_ratio = 2.0;
+            Rc::new(EquirectangularCamera::new(
+                look_from,
+                look_at,
+                v_up,
+                None,
+                Some(1.0),
+            ))
+        }
+        4 => {
+            // Both eyes stacked top/bottom, each one a 2:1 panorama. The eye distance assumes the
+            // scene is modelled in meters. The eyes merge from pole_merge degrees of latitude up
+            // so the poles don't show two images, 90 turns that off
+            let pole_merge: f32 = 60.0;
+            aspect_ratio = 1.0;
+            let mut cam =
+                OdsCamera::new(look_from, look_at, v_up, 0.064, aperture, None, Some(1.0));
+            cam.set_pole_merge(pole_merge);
+            cam.set_focus(&focus, &world);
+            Rc::new(cam)
+        }
+        5 => {
+            // 50mm lens at f/2.8, 1/60s and ISO 400 on a full frame body
+            let settings = PhysicalSettings::full_frame(50.0, 2.8, 1.0 / 60.0, 400.0);
+            aspect_ratio = settings.aspect_ratio();
+            let mut cam = PerspectiveCamera::physical(
+                look_from,
+                look_at,
+                v_up,
+                &settings,
+                dist_to_focus,
+                None,
+                Some(1.0),
+            );
+            configure(&mut cam);
+            Rc::new(cam)
+        }
+        6 => {
+            // Same settings as above but seen through a real lens, read from a prescription file
+            // or else the built in 50mm double Gauss
+            let lens_file: Option<&str> = None; //Some("../../lenses/wide.dat");
+            let lens = match lens_file {
+                Some(filename) => match LensSystem::load(filename) {
+                    Ok(lens) => lens,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return;
+                    }
+                },
+                None => LensSystem::double_gauss_50mm(),
+            };
+            let settings = PhysicalSettings::full_frame(50.0, 2.8, 1.0 / 60.0, 400.0);
+            aspect_ratio = settings.aspect_ratio();
+            let mut cam =
+                RealisticCamera::new(look_from, look_at, v_up, lens, &settings, None, Some(1.0));
+            cam.set_focus(&focus, &world);
+            Rc::new(cam)
+        }
+        _ => {
+            let mut cam = PerspectiveCamera::new(
+                look_from,
+                look_at,
+                v_up,
+                v_fov,
+                aspect_ratio,
+                aperture,
+                dist_to_focus,
+                None,
+                Some(1.0),
+            );
+            configure(&mut cam);
+            Rc::new(cam)
+        }
+    };
+    // Rolling shutter, for any camera. Lines are read out one after another over readout_time of
+    // the shutter interval: 1 top to bottom, 2 bottom to top, 3 left to right and 4 right to left.
+    // Anything else is a global shutter
+    let readout_time: f32 = 0.5;
+    let readout_direction = match 0 {
+        1 => Some(ReadoutDirection::TopToBottom),
+        2 => Some(ReadoutDirection::BottomToTop),
+        3 => Some(ReadoutDirection::LeftToRight),
+        4 => Some(ReadoutDirection::RightToLeft),
+        _ => None,
+    };
+    let cam: Rc<dyn Camera> = match readout_direction {
+        Some(direction) => Rc::new(RollingShutterCamera::new(
+            cam,
+            RollingShutter {
+                direction,
+                readout_time,
+            },
+        )),
+        None => cam,
+    };
+    // Image 2
+    let img_height = (img_width as f32 / aspect_ratio) as i32;
+    // Sampler
+    let mut sampler: Box<dyn Sampler> = match 0 {
+        1 => Box::new(StratifiedSampler::new(seed, samples_per_pixel)),
+        2 => Box::new(HaltonSampler::new(seed)),
+        3 => Box::new(SobolSampler::new(seed)),
+        _ => Box::new(IndependentSampler::new(seed)),
+    };
+    // Reconstruction filter, the radius is in pixels. The box only counts samples for the pixel
+    // they're in, the others also blend them into the neighbours for smoother edges. Mitchell
+    // is the sharpest of the smooth ones
+    let filter = match 0 {
+        1 => Filter::Tent(1.0),
+        2 => Filter::Gaussian {
+            radius: 1.5,
+            alpha: 2.0,
+        },
+        3 => Filter::Mitchell {
+            radius: 2.0,
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        },
+        4 => Filter::BlackmanHarris(1.5),
+        _ => Filter::Box(0.5),
+    };
     // Render
-    // Output will be in the PPM format, this sets up the metadata. P3 means the colors are in
-    // ASCII format, img width and height represent columns and rows and 255 is the max value
-    println!("P3\n{} {}\n255", img_width, img_height);
-
-    for j in (0..img_height).rev() {
-        eprint!("\rScanlines remaining: {}", j);
-        io::stderr().flush().unwrap();
-
-        for i in 0..img_width {
-            let mut pixel_color = Color::new(None, None, None);
-
-            for _s in 0..samples_per_pixel {
-                let u = (i as f32 + random::<f32>(0.0, 1.0)) / (img_width - 1) as f32;
-                let v = (j as f32 + random::<f32>(0.0, 1.0)) / (img_height - 1) as f32;
-                let ray = cam.get_ray(u, v);
-                pixel_color += ray_color(ray, &background, &world, max_depth);
+    // Passes of a few samples per pixel are added to the film until every pixel has
+    // samples_per_pixel samples (or converged). Every save_interval the film is written as a
+    // preview image and/or saved as a checkpoint. With resume, the checkpoint is loaded and the
+    // render continues from there, so raising samples_per_pixel adds more samples to an old
+    // render. The scene and camera have to be the same as when it was saved, a checkpoint with a
+    // different size, crop window, filter or seed is refused
+    let pass_samples = 4;
+    let preview: Option<&str> = None; //Some("preview.ppm");
+    let checkpoint: Option<&str> = None; //Some("render.ckpt");
+    let resume = false;
+    let save_interval = Duration::from_secs(30);
+    // The render also stops early when time_budget runs out or on Ctrl+C. The image so far is
+    // still written out (and checkpointed) as usual. A second Ctrl+C quits right away
+    let time_budget: Option<Duration> = None; //Some(Duration::from_secs(10 * 60));
+
+    // Renders only part of the frame, framed as in the full image. (x0, y0, x1, y1) in pixels,
+    // counting from the top left corner, x1 and y1 excluded. The output is just that region
+    let mut crop: Option<(i32, i32, i32, i32)> = None; //Some((100, 50, 200, 150));
+
+    // Traces a single pixel (x, y), also counting from the top left, and logs every bounce of
+    // every sample to stderr. Best with a low samples_per_pixel
+    let debug_pixel: Option<(i32, i32)> = None; //Some((300, 200));
+
+    // Auxiliary buffers (AOVs) of what the camera rays hit first: albedo, normal, depth, position,
+    // UVs and object/material IDs. Saved as layers of an EXR file along with the beauty pass
+    // and/or as separate PPM images starting with the prefix
+    let aov_exr: Option<&str> = None; //Some("render.exr");
+    let aov_images: Option<&str> = None; //Some("render");
+
+    // Denoising. With a denoiser, the image on stdout and the previews are denoised and the raw
+    // image can still be saved to a file. It uses the AOVs as guides, so those get collected too
+    let denoiser: Option<Denoiser> = None; //Some(Denoiser::default());
+    let raw_image: Option<&str> = None; //Some("raw.ppm");
+    let aovs = aov_exr.is_some() || aov_images.is_some() || denoiser.is_some();
+    // Tone mapping. The image gets exposure_compensation stops brighter (or darker) on top of the
+    // camera's exposure, then the tone mapper fits the radiance into what a display can show.
+    // Clamp blows bright lights out to flat white, Agx or Aces keep them
+    let exposure_compensation: f32 = 0.0;
+    let tone_mapper = match 0 {
+        1 => ToneMapper::Reinhard,
+        2 => ToneMapper::ExtendedReinhard(4.0),
+        3 => ToneMapper::Aces,
+        4 => ToneMapper::Agx,
+        _ => ToneMapper::Clamp,
+    };
+    let exposure = cam.exposure() * 2.0f32.powf(exposure_compensation);
+    // Encoding of the output image, Rec709 for video
+    let transfer = match 0 {
+        1 => TransferFunction::Rec709,
+        2 => TransferFunction::Gamma(2.0),
+        3 => TransferFunction::Linear,
+        _ => TransferFunction::Srgb,
+    };
+    // Post effects, applied in order on the exposed image before tone mapping. Any of them can
+    // be combined in the same list
+    let effects: Vec<PostEffect> = match 0 {
+        1 => vec![PostEffect::Bloom {
+            threshold: 1.0,
+            radius: 0.01,
+            intensity: 0.3,
+        }],
+        2 => vec![PostEffect::Glare {
+            threshold: 2.0,
+            blades: 6,
+            rotation: 0.0,
+            length: 0.05,
+            intensity: 0.2,
+        }],
+        3 => vec![PostEffect::Vignetting { strength: 0.5 }],
+        4 => vec![PostEffect::ChromaticAberration { strength: 0.003 }],
+        5 => vec![PostEffect::FilmGrain { amount: 0.05, seed }],
+        _ => vec![],
+    };
+
+    // Firefly suppression. Samples brighter than max_luminance (on screen, 1 being white) are
+    // dimmed down to it and/or samples more than outlier_sigma standard deviations above their
+    // pixel's mean are dimmed down to that. Either way the image gets a bit darker, how much is
+    // reported at the end
+    let max_luminance: Option<f32> = None; //Some(10.0);
+    let outlier_sigma: Option<f32> = None; //Some(4.0);
+    let firefly_suppression = FireflySuppression {
+        max_luminance: max_luminance.map(|l| l / exposure),
+        outlier_sigma,
+        outlier_min_samples: min_samples,
+    };
+
+    if let Some((x, y)) = debug_pixel {
+        crop = Some((x, y, x + 1, y + 1));
+    }
+
+    let (x0, y0, x1, y1) = match crop {
+        Some((x0, y0, x1, y1)) => (x0.max(0), y0.max(0), x1.min(img_width), y1.min(img_height)),
+        None => (0, 0, img_width, img_height),
+    };
+
+    if x1 <= x0 || y1 <= y0 {
+        eprintln!(
+            "The region {:?} has no pixels in the {}x{} image",
+            crop.unwrap(),
+            img_width,
+            img_height
+        );
+        return;
+    }
+
+    // Film pixel (i, j) is pixel (i + x_offset, j + y_offset) of the full frame, where j goes
+    // bottom to top
+    let x_offset = x0;
+    let y_offset = img_height - y1;
+    let mut film = Film::new(x1 - x0, y1 - y0, filter);
+    let checkpoint_settings = CheckpointSettings {
+        x_offset,
+        y_offset,
+        seed,
+    };
+    let view = ViewTransform {
+        exposure,
+        effects,
+        frame: Frame {
+            x_offset,
+            y_offset,
+            width: img_width,
+            height: img_height,
+        },
+        tone_mapper,
+        working_space,
+        transfer,
+    };
+
+    if let (true, Some(filename)) = (resume, checkpoint) {
+        match Film::load_checkpoint(filename, filter, &checkpoint_settings) {
+            Ok(saved) if saved.width() == film.width() && saved.height() == film.height() => {
+                film = saved
+            }
+            Ok(saved) => {
+                eprintln!(
+                    "The checkpoint is {}x{} but the image is {}x{}",
+                    saved.width(),
+                    saved.height(),
+                    film.width(),
+                    film.height()
+                );
+                return;
+            }
+            Err(e) => {
+                eprintln!("Could not load the checkpoint: {}", e);
+                return;
+            }
+        }
+    }
+    film.set_firefly_suppression(firefly_suppression);
+
+    // Film stats are in scene radiance, the threshold is about what ends up on screen. Scaling the
+    // luminance by the exposure scales the gamma corrected error by its square root
+    let threshold = adaptive_threshold.map(|t| t / exposure.sqrt());
+    let needs_samples = |stats: &PixelStats| {
+        stats.count() < samples_per_pixel
+            && !threshold.is_some_and(|t| {
+                stats.count() >= min_samples && stats.converged(t, samples_per_pixel)
+            })
+    };
+    let scene = Scene {
+        world: &world,
+        background: background.as_ref(),
+        camera_background: camera_background.as_ref(),
+        lights: &lights,
+    };
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let handler_cancelled = cancelled.clone();
+
+    if let Err(e) = ctrlc::set_handler(move || {
+        if handler_cancelled.swap(true, Ordering::SeqCst) {
+            process::exit(130);
+        }
+    }) {
+        eprintln!("Could not install the Ctrl+C handler: {}", e);
+    }
+
+    let start = Instant::now();
+    let out_of_time = || time_budget.is_some_and(|budget| start.elapsed() >= budget);
+    let debug = debug_pixel.is_some();
+    let mut pass = 0;
+    let mut last_save = Instant::now();
+
+    'render: loop {
+        let mut active_pixels = 0;
+
+        for j in (0..film.height()).rev() {
+            // Checked every scanline so stopping doesn't have to wait for a whole pass
+            if cancelled.load(Ordering::SeqCst) {
+                eprint!("\nCancelled, writing the image so far");
+                break 'render;
+            }
+            if out_of_time() {
+                eprint!("\nOut of time, writing the image so far");
+                break 'render;
+            }
+
+            for i in 0..film.width() {
+                if !needs_samples(film.stats(i, j)) {
+                    continue;
+                }
+                active_pixels += 1;
+
+                for _ in 0..pass_samples {
+                    if !needs_samples(film.stats(i, j)) {
+                        break;
+                    }
+                    let (x, y) = (i + x_offset, j + y_offset);
+                    let index = film.stats(i, j).count();
+                    sampler.start_sample(x, y, index);
+                    let (du, dv) = sampler.get_2d();
+                    let u = (x as f32 + du) / (img_width - 1) as f32;
+                    let v = (y as f32 + dv) / (img_height - 1) as f32;
+                    let mut sample_color = Color::new(None, None, None);
+
+                    if debug {
+                        eprintln!("Sample {} at ({}, {})", index, u, v);
+                    }
+                    if let Some((ray, weight)) = cam.get_ray(u, v, sampler.as_mut()) {
+                        if aovs {
+                            let mut hit_rec = HitRecord::empty();
+                            let aov = if world.hit(&ray, 0.001, INFINITY, &mut hit_rec) {
+                                AovSample::hit(&ray, &hit_rec)
+                            } else {
+                                AovSample::miss(&camera_background.radiance(&ray.direction()))
+                            };
+                            film.add_aov(i, j, &aov);
+                        }
+                        sample_color = ray_color(
+                            ray,
+                            &scene,
+                            RaySource::Camera,
+                            max_depth,
+                            sampler.as_mut(),
+                            debug,
+                        ) * weight;
+                    } else {
+                        if aovs {
+                            film.add_aov(i, j, &AovSample::miss(&Color::new(None, None, None)));
+                        }
+                        if debug {
+                            eprintln!("  Camera gave no ray");
+                        }
+                    }
+                    if debug {
+                        eprintln!("  Color: {}", sample_color);
+                    }
+                    film.add_sample(i, j, (du, dv), sample_color);
+                }
             }
-            write_color(pixel_color, samples_per_pixel) // PPM file contents
+        }
+
+        if active_pixels == 0 {
+            break;
+        }
+        pass += 1;
+
+        if !debug {
+            eprint!("\rPass {}, pixels sampled: {}      ", pass, active_pixels);
+            io::stderr().flush().unwrap();
+        }
+
+        if last_save.elapsed() >= save_interval {
+            save_progress(
+                &film,
+                preview,
+                checkpoint,
+                &checkpoint_settings,
+                denoiser.as_ref(),
+                &view,
+            );
+            last_save = Instant::now();
+        }
+    }
+    // Always leave a final checkpoint behind so more samples can be added later
+    save_progress(
+        &film,
+        preview,
+        checkpoint,
+        &checkpoint_settings,
+        denoiser.as_ref(),
+        &view,
+    );
+    eprintln!();
+
+    let firefly_stats = film.firefly_stats();
+    if firefly_stats.clamped + firefly_stats.rejected > 0 {
+        eprintln!(
+            "Fireflies: {} samples clamped and {} rejected out of {}, {:.3}% of the light removed",
+            firefly_stats.clamped,
+            firefly_stats.rejected,
+            firefly_stats.samples,
+            100.0 * firefly_stats.bias()
+        );
+    }
+    // PPM file contents
+    output_image(&film, denoiser.as_ref(), &view)
+        .write_ppm(&mut io::stdout().lock())
+        .unwrap();
+
+    if let Some(filename) = raw_image {
+        if let Err(e) = view.apply(&film.image()).save_ppm(filename) {
+            eprintln!("Could not write the raw image: {}", e);
+        }
+    }
+
+    if let Some(filename) = aov_exr {
+        if let Err(e) = film.save_exr(filename, exposure) {
+            eprintln!("Could not write the AOVs: {}", e);
         }
     }
+
+    if let Some(prefix) = aov_images {
+        if let Err(e) = film.save_aov_images(prefix, working_space) {
+            eprintln!("Could not write the AOV images: {}", e);
+        }
+    }
+
+    if let Some(filename) = sample_heatmap {
+        if let Err(e) = film.save_heatmap(filename, samples_per_pixel) {
+            eprintln!("Could not write the sample heatmap: {}", e);
+        }
+    }
+}
+
+// What gets written out: the film's image, denoised if there's a denoiser, through the view
+// transform
+fn output_image(film: &Film, denoiser: Option<&Denoiser>, view: &ViewTransform) -> Image {
+    let image = match denoiser {
+        Some(denoiser) => denoiser.denoise(film),
+        None => film.image(),
+    };
+
+    view.apply(&image)
+}
+
+fn save_progress(
+    film: &Film,
+    preview: Option<&str>,
+    checkpoint: Option<&str>,
+    checkpoint_settings: &CheckpointSettings,
+    denoiser: Option<&Denoiser>,
+    view: &ViewTransform,
+) {
+    if let Some(filename) = preview {
+        if let Err(e) = output_image(film, denoiser, view).save_ppm(filename) {
+            eprintln!("\nCould not write the preview: {}", e);
+        }
+    }
+
+    if let Some(filename) = checkpoint {
+        if let Err(e) = film.save_checkpoint(filename, checkpoint_settings) {
+            eprintln!("\nCould not save the checkpoint: {}", e);
+        }
+    }
+}
+
+// Everything a ray can run into
+struct Scene<'a> {
+    world: &'a dyn Hittable,
+    // Seen by rays that miss everything, and what lights the scene
+    background: &'a dyn Background,
+    // Seen instead by camera rays that miss everything
+    camera_background: &'a dyn Background,
+    lights: &'a [Rc<dyn Light>],
 }
 
-// This function writes the color of a single pixel to stdout
-fn write_color(pixel_color: Color, samples_per_pixel: i32) {
-    let mut r = pixel_color.x();
-    let mut g = pixel_color.y();
-    let mut b = pixel_color.z();
-    let scale = 1.0 / samples_per_pixel as f32;
-    r = (scale * r).sqrt();
-    g = (scale * g).sqrt();
-    b = (scale * b).sqrt();
-
-    println!(
-        "{} {} {}",
-        (256.0 * clamp(r, 0.0, 0.999)) as i32,
-        (256.0 * clamp(g, 0.0, 0.999)) as i32,
-        (256.0 * clamp(b, 0.0, 0.999)) as i32
-    )
+// Where a ray comes from, which decides what it sees when it misses everything
+#[derive(Clone, Copy)]
+enum RaySource {
+    Camera,
+    // A bounce that didn't sample the lights, like off a mirror
+    Scattered,
+    // A diffuse bounce that sampled the lights too, with the pdf it picked the ray's direction with
+    LightSampled(f32),
 }
 
-fn ray_color(ray: Ray, background: &Color, world: &dyn Hittable, depth: i32) -> Color {
+// Light arriving along the ray. An importance sampled background and the lights are also sampled
+// directly at diffuse surfaces (next event estimation) and both ways of finding them are weighted
+// by multiple importance sampling. 'source' tells what the ray finds by missing everything should
+// be weighted with
+fn ray_color(
+    ray: Ray,
+    scene: &Scene,
+    source: RaySource,
+    depth: i32,
+    sampler: &mut dyn Sampler,
+    debug: bool,
+) -> Color {
     let mut hit_rec = HitRecord::empty();
 
     if depth <= 0 {
+        if debug {
+            eprintln!("  Out of depth");
+        }
         return Color::new(None, None, None);
     }
 
-    if !world.hit(&ray, 0.001, INFINITY, &mut hit_rec) {
-        return *background;
+    if debug {
+        eprintln!(
+            "  Ray from {} towards {} (depth left {})",
+            ray.origin(),
+            ray.direction(),
+            depth
+        );
+    }
+
+    if !scene.world.hit(&ray, 0.001, INFINITY, &mut hit_rec) {
+        let direction = ray.direction();
+        let weight = |light_pdf: f32| match source {
+            RaySource::LightSampled(pdf) => power_heuristic(pdf, light_pdf),
+            _ => 1.0,
+        };
+        let background = match source {
+            RaySource::Camera => scene.camera_background,
+            _ => scene.background,
+        };
+        let mut radiance = background.radiance(&direction);
+
+        if background.importance_sampled() {
+            radiance *= weight(background.pdf(&direction));
+        }
+
+        // Lights far enough away to be run into, like the sun
+        for light in scene.lights {
+            radiance += light.radiance(&direction) * weight(light.pdf(&direction));
+        }
+
+        if debug {
+            eprintln!("    Missed, background {}", radiance);
+        }
+        return radiance;
     }
 
     let mut scattered = Ray::new(None, None, None);
@@ -146,14 +860,148 @@ fn ray_color(ray: Ray, background: &Color, world: &dyn Hittable, depth: i32) ->
         &hit_rec,
         &mut attenuation,
         &mut scattered,
+        sampler,
     ) {
+        if debug {
+            log_hit(&hit_rec, &emitted, None);
+        }
         return emitted;
     }
+    if debug {
+        log_hit(&hit_rec, &emitted, Some(&attenuation));
+    }
+
+    let material = hit_rec.material_ptr.as_ref().unwrap();
+    let mut direct = Color::new(None, None, None);
+    let mut next_source = RaySource::Scattered;
+    let background = scene.background;
 
-    emitted + attenuation * ray_color(scattered, background, world, depth - 1)
+    if background.importance_sampled() || !scene.lights.is_empty() {
+        if let Some((_, pdf)) = material.scattering(&ray, &hit_rec, &scattered.direction()) {
+            next_source = RaySource::LightSampled(pdf);
+
+            if background.importance_sampled() {
+                let (direction, light_pdf) = background.sample(sampler.get_2d());
+                let radiance = background.radiance(&direction);
+                let light = direct_light(
+                    &ray,
+                    &hit_rec,
+                    scene.world,
+                    &direction,
+                    INFINITY,
+                    &radiance,
+                    Some(light_pdf),
+                );
+
+                if debug {
+                    eprintln!(
+                        "    Background sampled towards {} (pdf {}), adds {}",
+                        direction, light_pdf, light
+                    );
+                }
+                direct += light;
+            }
+
+            for light in scene.lights {
+                let Some(sample) = light.sample(&hit_rec.point, sampler.get_2d()) else {
+                    continue;
+                };
+                let light = direct_light(
+                    &ray,
+                    &hit_rec,
+                    scene.world,
+                    &sample.direction,
+                    sample.distance,
+                    &sample.radiance,
+                    sample.pdf,
+                );
+
+                if debug {
+                    eprintln!(
+                        "    Light sampled towards {} (pdf {:?}), adds {}",
+                        sample.direction, sample.pdf, light
+                    );
+                }
+                direct += light;
+            }
+        }
+    }
+
+    emitted
+        + direct
+        + attenuation * ray_color(scattered, scene, next_source, depth - 1, sampler, debug)
+}
+
+// Light from a sampled direction that the surface scatters back along the ray, unless something is
+// in the way within 'distance'. Weighted against the surface finding it by scattering
+fn direct_light(
+    ray: &Ray,
+    hit_rec: &HitRecord,
+    world: &dyn Hittable,
+    direction: &Vec3,
+    distance: f32,
+    radiance: &Color,
+    light_pdf: Option<f32>,
+) -> Color {
+    let material = hit_rec.material_ptr.as_ref().unwrap();
+    let Some((f, scatter_pdf)) = material.scattering(ray, hit_rec, direction) else {
+        return Color::new(None, None, None);
+    };
+    let weight = match light_pdf {
+        Some(pdf) if pdf > 0.0 => power_heuristic(pdf, scatter_pdf) / pdf,
+        // Delta lights, nothing else can find them so the sample counts fully
+        None => 1.0,
+        _ => return Color::new(None, None, None),
+    };
+
+    if f.luminance() <= 0.0 {
+        return Color::new(None, None, None);
+    }
+
+    let shadow_ray = Ray::new(Some(hit_rec.point), Some(*direction), Some(ray.time()));
+
+    if world.hit(&shadow_ray, 0.001, distance, &mut HitRecord::empty()) {
+        return Color::new(None, None, None);
+    }
+
+    f * *radiance * weight
+}
+
+// Weight of a sample taken with pdf 'a' when pdf 'b' could have found it as well
+fn power_heuristic(a: f32, b: f32) -> f32 {
+    let a2 = a * a;
+    let b2 = b * b;
+
+    if a2 + b2 <= 0.0 {
+        return 0.0;
+    }
+
+    a2 / (a2 + b2)
+}
+
+fn log_hit(hit_rec: &HitRecord, emitted: &Color, attenuation: Option<&Color>) {
+    eprintln!(
+        "    Hit {} at t {}, point {}, normal {}, front face {}, uv ({}, {})",
+        hit_rec.object,
+        hit_rec.t,
+        hit_rec.point,
+        hit_rec.normal,
+        hit_rec.front_face,
+        hit_rec.u,
+        hit_rec.v
+    );
+    let material = hit_rec.material_ptr.as_ref().unwrap().name();
+
+    match attenuation {
+        Some(attenuation) => eprintln!(
+            "    {} emitted {}, scattered with attenuation {}",
+            material, emitted, attenuation
+        ),
+        None => eprintln!("    {} emitted {}, absorbed", material, emitted),
+    }
 }
 
-fn random_scene() -> HittableList {
+fn random_scene(rng: &mut Rng, working_space: ColorSpace) -> HittableList {
     let mut world = HittableList::new(None);
     /* let mat_ground = Rc::new(Lambertian::from(&Color::new(
         Some(0.5),
@@ -161,8 +1009,8 @@ fn random_scene() -> HittableList {
         Some(0.5),
     ))); */
     let checker = Rc::new(CheckerTexture::from(
-        Color::new(Some(0.2), Some(0.3), Some(0.1)),
-        Color::new(Some(0.9), Some(0.9), Some(0.9)),
+        working_space.convert_linear_srgb(Color::new(Some(0.2), Some(0.3), Some(0.1))),
+        working_space.convert_linear_srgb(Color::new(Some(0.9), Some(0.9), Some(0.9))),
     ));
     world.add(Rc::new(Sphere::new(
         Point3::new(None, Some(-1000.0), None),
@@ -172,26 +1020,29 @@ fn random_scene() -> HittableList {
 
     for a in -11..11 {
         for b in -11..11 {
-            let choose_mat = random::<f32>(0.0, 1.0);
+            let choose_mat = random::<f32>(rng, 0.0, 1.0);
             let center = Point3::new(
-                Some(a as f32 + 0.9 * random::<f32>(0.0, 1.0)),
+                Some(a as f32 + 0.9 * random::<f32>(rng, 0.0, 1.0)),
                 Some(0.2),
-                Some(b as f32 + 0.9 * random::<f32>(0.0, 1.0)),
+                Some(b as f32 + 0.9 * random::<f32>(rng, 0.0, 1.0)),
             );
 
             if (center - Point3::new(Some(4.0), Some(0.2), None)).len() > 0.9 {
                 let mat_sphere: Rc<dyn Material>;
 
                 if choose_mat < 0.8 {
-                    let albedo = Color::random(None, None) * Color::random(None, None);
+                    let albedo = working_space.convert_linear_srgb(
+                        Color::random(rng, None, None) * Color::random(rng, None, None),
+                    );
                     mat_sphere = Rc::new(Lambertian::from(&albedo));
-                    let center2 = center + Vec3::new(None, Some(random(0.0, 0.5)), None);
+                    let center2 = center + Vec3::new(None, Some(random(rng, 0.0, 0.5)), None);
                     world.add(Rc::new(MovingSphere::new(
                         center, center2, 0.0, 1.0, 0.2, mat_sphere,
                     )));
                 } else if choose_mat < 0.95 {
-                    let albedo = Color::random(Some(0.5), Some(1.0));
-                    let fuzz = random::<f32>(0.0, 0.5);
+                    let albedo =
+                        working_space.convert_linear_srgb(Color::random(rng, Some(0.5), Some(1.0)));
+                    let fuzz = random::<f32>(rng, 0.0, 0.5);
                     mat_sphere = Rc::new(Metal::new(albedo, fuzz));
                     world.add(Rc::new(Sphere::new(center, 0.2, mat_sphere)));
                 } else {
@@ -208,17 +1059,18 @@ fn random_scene() -> HittableList {
         1.0,
         mat1,
     )));
-    let mat2 = Rc::new(Lambertian::from(&Color::new(
-        Some(0.4),
-        Some(0.2),
-        Some(0.1),
-    )));
+    let mat2 = Rc::new(Lambertian::from(
+        &working_space.convert_linear_srgb(Color::new(Some(0.4), Some(0.2), Some(0.1))),
+    ));
     world.add(Rc::new(Sphere::new(
         Point3::new(Some(-4.0), Some(1.0), None),
         1.0,
         mat2,
     )));
-    let mat3 = Rc::new(Metal::new(Color::new(Some(0.7), Some(0.6), Some(0.5)), 0.0));
+    let mat3 = Rc::new(Metal::new(
+        working_space.convert_linear_srgb(Color::new(Some(0.7), Some(0.6), Some(0.5))),
+        0.0,
+    ));
     world.add(Rc::new(Sphere::new(
         Point3::new(Some(4.0), Some(1.0), None),
         1.0,
@@ -228,11 +1080,11 @@ fn random_scene() -> HittableList {
     return world;
 }
 
-fn two_spheres() -> HittableList {
+fn two_spheres(working_space: ColorSpace) -> HittableList {
     let mut objects = HittableList::new(None);
     let checker = Rc::new(CheckerTexture::from(
-        Color::new(Some(0.2), Some(0.3), Some(0.1)),
-        Color::new(Some(0.9), Some(0.9), Some(0.9)),
+        working_space.convert_linear_srgb(Color::new(Some(0.2), Some(0.3), Some(0.1))),
+        working_space.convert_linear_srgb(Color::new(Some(0.9), Some(0.9), Some(0.9))),
     ));
     objects.add(Rc::new(Sphere::new(
         Point3::new(None, Some(-10.0), None),
@@ -248,9 +1100,9 @@ fn two_spheres() -> HittableList {
     objects
 }
 
-fn two_perlin_spheres() -> HittableList {
+fn two_perlin_spheres(rng: &mut Rng) -> HittableList {
     let mut objects = HittableList::new(None);
-    let perlin_texture = Rc::new(NoiseTexture::new(Some(4.0)));
+    let perlin_texture = Rc::new(NoiseTexture::new(rng, Some(4.0)));
     objects.add(Rc::new(Sphere::new(
         Point3::new(None, Some(-1000.0), None),
         1000.0,
@@ -265,17 +1117,20 @@ fn two_perlin_spheres() -> HittableList {
     objects
 }
 
-fn earth() -> HittableList {
-    let earth_texture = Rc::new(ImageTexture::new("../../textures/earthmap.jpg"));
+fn earth(working_space: ColorSpace) -> HittableList {
+    let earth_texture = Rc::new(ImageTexture::new(
+        "../../textures/earthmap.jpg",
+        working_space,
+    ));
     let earth_surface = Rc::new(Lambertian::new(earth_texture));
     let globe = Rc::new(Sphere::new(Vec3::new(None, None, None), 2.0, earth_surface));
 
     HittableList::new(Some(globe))
 }
 
-fn simple_light() -> HittableList {
+fn simple_light(rng: &mut Rng, working_space: ColorSpace) -> HittableList {
     let mut objects = HittableList::new(None);
-    let perlin_texture = Rc::new(NoiseTexture::new(Some(4.0)));
+    let perlin_texture = Rc::new(NoiseTexture::new(rng, Some(4.0)));
     objects.add(Rc::new(Sphere::new(
         Point3::new(None, Some(-1000.0), None),
         1000.0,
@@ -286,11 +1141,9 @@ fn simple_light() -> HittableList {
         2.0,
         Rc::new(Lambertian::new(perlin_texture)),
     )));
-    let diff_light = Rc::new(DiffuseLight::from(Color::new(
-        Some(4.0),
-        Some(4.0),
-        Some(4.0),
-    )));
+    let diff_light = Rc::new(DiffuseLight::from(
+        working_space.convert_linear_srgb(Color::new(Some(4.0), Some(4.0), Some(4.0))),
+    ));
     objects.add(Rc::new(XYRect::new(
         3.0,
         5.0,
@@ -303,28 +1156,20 @@ fn simple_light() -> HittableList {
     objects
 }
 
-fn cornell_box() -> HittableList {
+fn cornell_box(working_space: ColorSpace) -> HittableList {
     let mut objects = HittableList::new(None);
-    let red = Rc::new(Lambertian::from(&Color::new(
-        Some(0.65),
-        Some(0.05),
-        Some(0.05),
-    )));
-    let white = Rc::new(Lambertian::from(&Color::new(
-        Some(0.73),
-        Some(0.73),
-        Some(0.73),
-    )));
-    let green = Rc::new(Lambertian::from(&Color::new(
-        Some(0.12),
-        Some(0.45),
-        Some(0.15),
-    )));
-    let light = Rc::new(DiffuseLight::from(Color::new(
-        Some(15.0),
-        Some(15.0),
-        Some(15.0),
-    )));
+    let red = Rc::new(Lambertian::from(
+        &working_space.convert_linear_srgb(Color::new(Some(0.65), Some(0.05), Some(0.05))),
+    ));
+    let white = Rc::new(Lambertian::from(
+        &working_space.convert_linear_srgb(Color::new(Some(0.73), Some(0.73), Some(0.73))),
+    ));
+    let green = Rc::new(Lambertian::from(
+        &working_space.convert_linear_srgb(Color::new(Some(0.12), Some(0.45), Some(0.15))),
+    ));
+    let light = Rc::new(DiffuseLight::from(
+        working_space.convert_linear_srgb(Color::new(Some(15.0), Some(15.0), Some(15.0))),
+    ));
 
     objects.add(Rc::new(YZRect::new(
         0.0,
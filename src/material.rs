@@ -1,8 +1,16 @@
+use crate::rtweekend::{short_type_name, IdCounter, PI};
+use crate::sampler::Sampler;
 use crate::texture::SolidColor;
 use crate::texture::Texture;
-use crate::{random, Color, HitRecord, Point3, Ray, Vec3};
+use crate::{Color, HitRecord, Point3, Ray, Vec3};
 use std::rc::Rc;
 
+// Every material gets an ID when it's created
+fn next_material_id() -> u32 {
+    static MATERIAL_IDS: IdCounter = IdCounter::new();
+    MATERIAL_IDS.next()
+}
+
 pub trait Material {
     fn scatter(
         &self,
@@ -10,25 +18,52 @@ pub trait Material {
         hit_rec: &HitRecord,
         attenuation: &mut Color,
         scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
     ) -> bool;
 
     fn emitted(&self, u: f32, v: f32, point: &Point3) -> Color {
         Color::new(None, None, None)
     }
+
+    // For diffuse materials, the ones worth sampling lights directly for: how much of the light
+    // arriving from 'direction' gets scattered back along the ray (BRDF times cosine), and the pdf
+    // of scatter() picking that direction. None for the rest
+    fn scattering(
+        &self,
+        _ray_input: &Ray,
+        _hit_rec: &HitRecord,
+        _direction: &Vec3,
+    ) -> Option<(Color, f32)> {
+        None
+    }
+
+    // Base color of the surface, without any lighting. Written out as an AOV
+    fn albedo(&self, hit_rec: &HitRecord) -> Color;
+
+    fn id(&self) -> u32;
+
+    fn name(&self) -> &'static str {
+        short_type_name::<Self>()
+    }
 }
 
 pub struct Lambertian {
     albedo: Rc<dyn Texture>,
+    id: u32,
 }
 
 impl Lambertian {
     pub fn from(albedo: &Color) -> Lambertian {
         Lambertian {
             albedo: Rc::new(SolidColor::new(Some(*albedo))),
+            id: next_material_id(),
         }
     }
     pub fn new(albedo: Rc<dyn Texture>) -> Lambertian {
-        Lambertian { albedo }
+        Lambertian {
+            albedo,
+            id: next_material_id(),
+        }
     }
 }
 
@@ -39,8 +74,9 @@ impl Material for Lambertian {
         hit_rec: &HitRecord,
         attenuation: &mut Color,
         scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
     ) -> bool {
-        let mut scatter_direction = hit_rec.normal + Vec3::random_unit_vector();
+        let mut scatter_direction = hit_rec.normal + Vec3::sample_unit_vector(sampler.get_2d());
 
         if scatter_direction.near_zero() {
             scatter_direction = hit_rec.normal;
@@ -55,11 +91,33 @@ impl Material for Lambertian {
 
         true
     }
+
+    // scatter() picks directions with a cosine distribution, the BRDF is albedo / pi
+    fn scattering(
+        &self,
+        _ray_input: &Ray,
+        hit_rec: &HitRecord,
+        direction: &Vec3,
+    ) -> Option<(Color, f32)> {
+        let cosine = hit_rec.normal.dot(&Vec3::unit_vector(*direction)).max(0.0);
+        let albedo = self.albedo.value(hit_rec.u, hit_rec.v, &hit_rec.point);
+
+        Some((albedo * (cosine / PI), cosine / PI))
+    }
+
+    fn albedo(&self, hit_rec: &HitRecord) -> Color {
+        self.albedo.value(hit_rec.u, hit_rec.v, &hit_rec.point)
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
 }
 
 pub struct Metal {
     albedo: Color,
     fuzz: f32,
+    id: u32,
 }
 
 impl Metal {
@@ -67,6 +125,7 @@ impl Metal {
         Metal {
             albedo,
             fuzz: if fuzz < 1.0 { fuzz } else { 1.0 },
+            id: next_material_id(),
         }
     }
 }
@@ -78,27 +137,41 @@ impl Material for Metal {
         hit_rec: &HitRecord,
         attenuation: &mut Vec3,
         scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
     ) -> bool {
         let reflected = Vec3::reflect(&Vec3::unit_vector(ray_input.direction()), &hit_rec.normal);
         *scattered = Ray::new(
             Some(hit_rec.point),
-            Some(reflected + Vec3::random_in_unit_sphere() * self.fuzz),
+            Some(
+                reflected
+                    + Vec3::sample_in_unit_sphere(sampler.get_2d(), sampler.get_1d()) * self.fuzz,
+            ),
             Some(ray_input.time()),
         );
         *attenuation = self.albedo;
 
         scattered.direction().dot(&hit_rec.normal) > 0.0
     }
+
+    fn albedo(&self, _hit_rec: &HitRecord) -> Color {
+        self.albedo
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
 }
 
 pub struct Dielectric {
     refraction_index: f32,
+    id: u32,
 }
 
 impl Dielectric {
     pub fn new(r_in: f32) -> Dielectric {
         Dielectric {
             refraction_index: r_in,
+            id: next_material_id(),
         }
     }
 
@@ -118,6 +191,7 @@ impl Material for Dielectric {
         hit_rec: &HitRecord,
         attenuation: &mut Vec3,
         scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
     ) -> bool {
         *attenuation = Color::new(Some(1.0), Some(1.0), Some(1.0));
         let refraction_ratio = if hit_rec.front_face {
@@ -131,8 +205,7 @@ impl Material for Dielectric {
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
         let direction: Vec3;
 
-        if cannot_refract
-            || Dielectric::reflectance(cos_theta, refraction_ratio) > random::<f32>(0.0, 1.0)
+        if cannot_refract || Dielectric::reflectance(cos_theta, refraction_ratio) > sampler.get_1d()
         {
             direction = Vec3::reflect(&unit_direction, &hit_rec.normal);
         } else {
@@ -143,21 +216,35 @@ impl Material for Dielectric {
 
         true
     }
+
+    // Glass is clear, white keeps denoisers from blurring what's seen through it
+    fn albedo(&self, _hit_rec: &HitRecord) -> Color {
+        Color::new(Some(1.0), Some(1.0), Some(1.0))
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
 }
 
 pub struct DiffuseLight {
     emit: Rc<dyn Texture>,
+    id: u32,
 }
 
 impl DiffuseLight {
     pub fn from(color: Color) -> DiffuseLight {
         DiffuseLight {
             emit: Rc::new(SolidColor::new(Some(color))),
+            id: next_material_id(),
         }
     }
 
     pub fn new(emit: Rc<dyn Texture>) -> DiffuseLight {
-        DiffuseLight { emit }
+        DiffuseLight {
+            emit,
+            id: next_material_id(),
+        }
     }
 }
 
@@ -168,6 +255,7 @@ impl Material for DiffuseLight {
         hit_rec: &HitRecord,
         attenuation: &mut Color,
         scattered: &mut Ray,
+        _sampler: &mut dyn Sampler,
     ) -> bool {
         return false;
     }
@@ -175,4 +263,20 @@ impl Material for DiffuseLight {
     fn emitted(&self, u: f32, v: f32, point: &Point3) -> Color {
         self.emit.value(u, v, point)
     }
+
+    // The emitted color, brought down to at most 1 so it stays a valid albedo
+    fn albedo(&self, hit_rec: &HitRecord) -> Color {
+        let emitted = self.emit.value(hit_rec.u, hit_rec.v, &hit_rec.point);
+        let brightest = emitted.x().max(emitted.y()).max(emitted.z());
+
+        if brightest > 1.0 {
+            emitted / brightest
+        } else {
+            emitted
+        }
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
 }
@@ -1,4 +1,5 @@
 use crate::aabb::AABB;
+use crate::hittable::next_object_id;
 use crate::{HitRecord, Hittable, Material, Point3, Ray, Vec3};
 use std::rc::Rc;
 
@@ -9,6 +10,7 @@ pub struct MovingSphere {
     time1: f32,
     radius: f32,
     mat_ptr: Rc<dyn Material>,
+    id: u32,
 }
 
 impl MovingSphere {
@@ -27,6 +29,7 @@ impl MovingSphere {
             time1,
             radius,
             mat_ptr,
+            id: next_object_id(),
         }
     }
 
@@ -64,6 +67,8 @@ impl Hittable for MovingSphere {
         let outward_normal = (hit_record.point - self.center(ray.time())) / self.radius;
         hit_record.set_face_normal(ray, &outward_normal);
         hit_record.material_ptr = Some(self.mat_ptr.clone());
+        hit_record.object = self.name();
+        hit_record.object_id = self.id;
 
         return true;
     }
@@ -1,3 +1,4 @@
+use crate::rtweekend::Rng;
 use crate::{random, Point3, Vec3};
 
 pub struct Perlin {
@@ -10,38 +11,38 @@ pub struct Perlin {
 impl Perlin {
     const POINT_COUNT: i32 = 256;
 
-    fn perlin_generate_perm() -> Vec<i32> {
+    fn perlin_generate_perm(rng: &mut Rng) -> Vec<i32> {
         let mut p: Vec<i32> = vec![0; Self::POINT_COUNT as usize];
 
         for i in 0..Self::POINT_COUNT {
             p[i as usize] = i;
         }
 
-        Self::permute(&mut p, Self::POINT_COUNT);
+        Self::permute(rng, &mut p, Self::POINT_COUNT);
         p
     }
 
-    fn permute(p: &mut Vec<i32>, n: i32) {
+    fn permute(rng: &mut Rng, p: &mut Vec<i32>, n: i32) {
         for i in (1..n - 1).rev() {
-            let target = random::<i32>(0, i);
+            let target = random::<i32>(rng, 0, i);
             let temp = p[i as usize];
             p[i as usize] = p[target as usize];
             p[target as usize] = temp;
         }
     }
 
-    pub fn new() -> Perlin {
+    pub fn new(rng: &mut Rng) -> Perlin {
         let mut ran_vec: Vec<Vec3> = vec![Vec3::new(None, None, None); Self::POINT_COUNT as usize];
 
         for i in 0..Self::POINT_COUNT {
-            ran_vec[i as usize] = Vec3::unit_vector(Vec3::random(Some(-1.0), Some(1.0)));
+            ran_vec[i as usize] = Vec3::unit_vector(Vec3::random(rng, Some(-1.0), Some(1.0)));
         }
 
         Perlin {
             ran_vec,
-            perm_x: Self::perlin_generate_perm(),
-            perm_y: Self::perlin_generate_perm(),
-            perm_z: Self::perlin_generate_perm(),
+            perm_x: Self::perlin_generate_perm(rng),
+            perm_y: Self::perlin_generate_perm(rng),
+            perm_z: Self::perlin_generate_perm(rng),
         }
     }
 
@@ -0,0 +1,288 @@
+use crate::image::Image;
+use crate::rtweekend::{degrees_to_radians, random, seeded_rng, PI};
+use crate::Color;
+
+// Where the image sits in the full frame: image pixel (i, j) is pixel (i + x_offset,
+// j + y_offset) of a width x height frame. Effects that depend on the distance to the center
+// use it, so a crop gets the same look as the full render
+#[derive(Clone, Copy)]
+pub struct Frame {
+    pub x_offset: i32,
+    pub y_offset: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Frame {
+    fn half_diagonal(&self) -> f32 {
+        0.5 * ((self.width * self.width + self.height * self.height) as f32).sqrt()
+    }
+
+    // Position of an image pixel's center relative to the center of the frame, in half
+    // diagonals (the corners are at distance 1)
+    fn position(&self, i: f32, j: f32) -> (f32, f32) {
+        let half_diagonal = self.half_diagonal();
+
+        (
+            (i + self.x_offset as f32 + 0.5 - 0.5 * self.width as f32) / half_diagonal,
+            (j + self.y_offset as f32 + 0.5 - 0.5 * self.height as f32) / half_diagonal,
+        )
+    }
+
+    // Image pixel at a position given like the ones above
+    fn pixel(&self, x: f32, y: f32) -> (f32, f32) {
+        let half_diagonal = self.half_diagonal();
+
+        (
+            x * half_diagonal + 0.5 * self.width as f32 - 0.5 - self.x_offset as f32,
+            y * half_diagonal + 0.5 * self.height as f32 - 0.5 - self.y_offset as f32,
+        )
+    }
+}
+
+// Effects applied to the linear image after exposure and before tone mapping, in the order
+// they're listed in. Thresholds are luminances on screen, 1 being white. Sizes are fractions of
+// the frame width so they look the same at any resolution
+#[derive(Clone, Copy)]
+pub enum PostEffect {
+    // Light above the threshold bleeds into its surroundings, as it does in the eye and in
+    // lenses. 'radius' is the standard deviation of the blur
+    Bloom {
+        threshold: f32,
+        radius: f32,
+        intensity: f32,
+    },
+    // Star shaped streaks around bright lights, from diffraction on the diaphragm blades. Each
+    // blade edge gives a pair of streaks perpendicular to it, edges facing each other share
+    // theirs, so an even number of blades gives that many streaks and an odd one twice as many.
+    // Use the same 'blades' and 'rotation' (in degrees) as the camera's PolygonalAperture
+    Glare {
+        threshold: f32,
+        blades: i32,
+        rotation: f32,
+        length: f32,
+        intensity: f32,
+    },
+    // Natural vignetting, the cos^4 falloff towards the edges of the frame. 'strength' is the
+    // tangent of the angle between the optical axis and the corners, so wider lenses fall off more
+    Vignetting {
+        strength: f32,
+    },
+    // Lateral chromatic aberration, red is magnified and blue shrunk by 'strength' around the
+    // center, which leaves colored fringes on edges towards the corners
+    ChromaticAberration {
+        strength: f32,
+    },
+    // Film grain, random brightness variations with 'amount' standard deviation. The same seed
+    // gives the same grain
+    FilmGrain {
+        amount: f32,
+        seed: u64,
+    },
+}
+
+impl PostEffect {
+    pub fn apply(&self, image: &Image, frame: &Frame) -> Image {
+        match *self {
+            PostEffect::Bloom {
+                threshold,
+                radius,
+                intensity,
+            } => {
+                let sigma = radius * frame.width as f32;
+                let blurred = gaussian_blur(&bright_pass(image, threshold), sigma);
+
+                Image::from_fn(image.width(), image.height(), &|i, j| {
+                    image.get(i, j) + blurred.get(i, j) * intensity
+                })
+            }
+            PostEffect::Glare {
+                threshold,
+                blades,
+                rotation,
+                length,
+                intensity,
+            } => {
+                let bright = bright_pass(image, threshold);
+                let streaks = glare(&bright, blades, rotation, length * frame.width as f32);
+
+                Image::from_fn(image.width(), image.height(), &|i, j| {
+                    image.get(i, j) + streaks.get(i, j) * intensity
+                })
+            }
+            PostEffect::Vignetting { strength } => {
+                Image::from_fn(image.width(), image.height(), &|i, j| {
+                    let color = image.get(i, j);
+                    let (x, y) = frame.position(i as f32, j as f32);
+                    let tan = strength * (x * x + y * y).sqrt();
+                    let cos2 = 1.0 / (1.0 + tan * tan);
+
+                    color * (cos2 * cos2)
+                })
+            }
+            PostEffect::ChromaticAberration { strength } => {
+                Image::from_fn(image.width(), image.height(), &|i, j| {
+                    let color = image.get(i, j);
+                    let (x, y) = frame.position(i as f32, j as f32);
+                    // Where the red and blue that end up here came from
+                    let (ri, rj) = frame.pixel(x / (1.0 + strength), y / (1.0 + strength));
+                    let (bi, bj) = frame.pixel(x / (1.0 - strength), y / (1.0 - strength));
+
+                    Color::new(
+                        Some(bilinear(image, ri, rj).x()),
+                        Some(color.y()),
+                        Some(bilinear(image, bi, bj).z()),
+                    )
+                })
+            }
+            PostEffect::FilmGrain { amount, seed } => {
+                let mut rng = seeded_rng(seed, 0);
+                let mut result = image.clone();
+
+                for j in 0..image.height() {
+                    for i in 0..image.width() {
+                        // Sum of uniforms, close enough to a normal distribution
+                        let noise: f32 = (0..4).map(|_| random(&mut rng, -1.0, 1.0)).sum::<f32>()
+                            * (3.0f32 / 4.0).sqrt();
+                        let color = image.get(i, j) * (1.0 + amount * noise);
+
+                        result.set(
+                            i,
+                            j,
+                            Color::new(
+                                Some(color.x().max(0.0)),
+                                Some(color.y().max(0.0)),
+                                Some(color.z().max(0.0)),
+                            ),
+                        );
+                    }
+                }
+
+                result
+            }
+        }
+    }
+}
+
+// Just the light above the threshold, keeping its color
+fn bright_pass(image: &Image, threshold: f32) -> Image {
+    image.map(&|color| {
+        let luminance = color.luminance();
+
+        if luminance <= threshold {
+            return Color::new(None, None, None);
+        }
+
+        color * ((luminance - threshold) / luminance)
+    })
+}
+
+// Separable Gaussian blur, cut off at 3 standard deviations. Light falling off the edges is lost
+fn gaussian_blur(image: &Image, sigma: f32) -> Image {
+    let radius = (3.0 * sigma).ceil() as i32;
+
+    if radius < 1 {
+        return image.clone();
+    }
+
+    let kernel: Vec<f32> = (-radius..=radius)
+        .map(|x| (-(x * x) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+    let kernel: Vec<f32> = kernel.iter().map(|w| w / sum).collect();
+
+    let blur = |image: &Image, dx: i32, dy: i32| {
+        Image::from_fn(image.width(), image.height(), &|i, j| {
+            let mut color = Color::new(None, None, None);
+
+            for (k, weight) in kernel.iter().enumerate() {
+                let offset = k as i32 - radius;
+                let x = i + offset * dx;
+                let y = j + offset * dy;
+
+                if x >= 0 && x < image.width() && y >= 0 && y < image.height() {
+                    color += image.get(x, y) * *weight;
+                }
+            }
+
+            color
+        })
+    };
+
+    blur(&blur(image, 1, 0), 0, 1)
+}
+
+// Spreads every bright pixel along the streaks, fading out quadratically over 'length' pixels.
+// Each streak carries an equal share of the pixel's light
+fn glare(bright: &Image, blades: i32, rotation: f32, length: f32) -> Image {
+    let blades = blades.max(3);
+    // Opposite edges are parallel when the count is even, their streaks are the same
+    let flips: &[f32] = if blades % 2 == 0 { &[0.0] } else { &[0.0, PI] };
+    let streaks = blades * flips.len() as i32;
+    let steps = length.ceil() as i32;
+    let mut result = Image::new(bright.width(), bright.height());
+
+    if steps < 1 {
+        return result;
+    }
+
+    let falloff: Vec<f32> = (1..=steps)
+        .map(|s| {
+            let t = 1.0 - s as f32 / (steps + 1) as f32;
+            t * t
+        })
+        .collect();
+    let falloff_sum: f32 = falloff.iter().sum();
+    // Perpendicular to the blade edges, see PolygonalAperture
+    let mut directions = Vec::new();
+
+    for k in 0..blades {
+        for flip in flips {
+            let angle =
+                degrees_to_radians(rotation) + (k as f32 + 0.5) * 2.0 * PI / blades as f32 + flip;
+            directions.push((angle.cos(), angle.sin()));
+        }
+    }
+
+    for j in 0..bright.height() {
+        for i in 0..bright.width() {
+            let color = bright.get(i, j);
+
+            if color.luminance() <= 0.0 {
+                continue;
+            }
+
+            let share = color / (streaks as f32 * falloff_sum);
+
+            for (dx, dy) in &directions {
+                for (s, weight) in falloff.iter().enumerate() {
+                    let distance = (s + 1) as f32;
+                    let x = (i as f32 + dx * distance).round() as i32;
+                    let y = (j as f32 + dy * distance).round() as i32;
+
+                    if x < 0 || x >= bright.width() || y < 0 || y >= bright.height() {
+                        break;
+                    }
+
+                    result.set(x, y, result.get(x, y) + share * *weight);
+                }
+            }
+        }
+    }
+
+    result
+}
+
+// Color at a fractional pixel position, clamped to the edges
+fn bilinear(image: &Image, x: f32, y: f32) -> Color {
+    let x = x.clamp(0.0, (image.width() - 1) as f32);
+    let y = y.clamp(0.0, (image.height() - 1) as f32);
+    let i = (x as i32).min(image.width() - 2).max(0);
+    let j = (y as i32).min(image.height() - 2).max(0);
+    let tx = x - i as f32;
+    let ty = y - j as f32;
+    let get = |i: i32, j: i32| image.get(i.min(image.width() - 1), j.min(image.height() - 1));
+
+    (get(i, j) * (1.0 - tx) + get(i + 1, j) * tx) * (1.0 - ty)
+        + (get(i, j + 1) * (1.0 - tx) + get(i + 1, j + 1) * tx) * ty
+}
@@ -1,4 +1,11 @@
-use rand::Rng;
+use rand::Rng as _;
+use rand_pcg::Pcg32;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+// Every random number in the renderer comes from one of these, never from a global generator.
+// Each pixel gets its own generator seeded from the render seed and the pixel's index, so the
+// same seed always gives the same image no matter the order pixels are rendered in
+pub type Rng = Pcg32;
 
 pub const INFINITY: f32 = f32::INFINITY;
 pub const PI: f32 = std::f32::consts::PI;
@@ -8,14 +15,24 @@ pub fn degrees_to_radians(degrees: f32) -> f32 {
 }
 
 pub fn random<T: std::cmp::PartialOrd + rand::distributions::uniform::SampleUniform>(
+    rng: &mut Rng,
     start: T,
     end: T,
 ) -> T {
-    let mut rng = rand::thread_rng();
-
     rng.gen_range(start..end)
 }
 
+// Generator for one independent stream of random numbers ('stream' can be a pixel index, a scene
+// element, etc). The seed goes through SplitMix64 first so nearby seeds don't give similar
+// sequences
+pub fn seeded_rng(seed: u64, stream: u64) -> Rng {
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+    Pcg32::new(z ^ (z >> 31), stream)
+}
+
 pub fn clamp(x: f32, min: f32, max: f32) -> f32 {
     return if x < min {
         min
@@ -25,3 +42,23 @@ pub fn clamp(x: f32, min: f32, max: f32) -> f32 {
         x
     };
 }
+
+// Name of a type without its module path, e.g. "Lambertian". Used for debug output
+pub fn short_type_name<T: ?Sized>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+// Hands out IDs in creation order starting at 1, so 0 is free to mean none. Scenes are built the
+// same way every run, which keeps the IDs stable between renders
+pub struct IdCounter(AtomicU32);
+
+impl IdCounter {
+    pub const fn new() -> IdCounter {
+        IdCounter(AtomicU32::new(1))
+    }
+
+    pub fn next(&self) -> u32 {
+        self.0.fetch_add(1, Ordering::Relaxed)
+    }
+}
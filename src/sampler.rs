@@ -0,0 +1,467 @@
+use crate::rtweekend::{random, seeded_rng, Rng};
+
+// Source of the random numbers used to render a pixel sample. Every sample is a point in a
+// high dimensional space: the first two dimensions jitter the position inside the pixel, then
+// come time, lens and one or more dimensions per bounce. Asking for them through a sampler
+// instead of calling random() lets low discrepancy sequences spread them out much more evenly
+// than pure random numbers, which means less noise for the same amount of samples.
+//
+// Samples are random access, the same pixel and sample index always give the same numbers no
+// matter what was rendered before
+pub trait Sampler {
+    // Has to be called before the first get_1d()/get_2d() of every sample
+    fn start_sample(&mut self, x: i32, y: i32, index: i32);
+    fn get_1d(&mut self) -> f32;
+    fn get_2d(&mut self) -> (f32, f32);
+}
+
+// Combines a few values into a well mixed 64 bit hash (SplitMix64 finalizer after each one)
+fn hash(values: &[u64]) -> u64 {
+    let mut h: u64 = 0;
+
+    for value in values {
+        h ^= value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        h ^= h >> 31;
+    }
+
+    h
+}
+
+// Generator for the numbers that aren't taken from a sequence
+fn sample_rng(seed: u64, x: i32, y: i32, index: i32) -> Rng {
+    seeded_rng(hash(&[seed, x as u64, y as u64, index as u64]), 0)
+}
+
+// Turns 32 random bits into a float in [0,1)
+fn to_unit_float(bits: u32) -> f32 {
+    (bits >> 8) as f32 / (1u32 << 24) as f32
+}
+
+// Plain random numbers, every dimension independent of the others
+pub struct IndependentSampler {
+    seed: u64,
+    rng: Rng,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> IndependentSampler {
+        IndependentSampler {
+            seed,
+            rng: seeded_rng(seed, 0),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, x: i32, y: i32, index: i32) {
+        self.rng = sample_rng(self.seed, x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        random::<f32>(&mut self.rng, 0.0, 1.0)
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+// Jittered sampling. The pixel's samples are split into strata (a grid for 2D requests) and
+// every sample lands somewhere random inside its own stratum. Each dimension shuffles which
+// sample gets which stratum so dimensions don't end up correlated. When samples_per_pixel isn't a
+// perfect square the grid gets an extra row, and the cells left over are just the ones no sample
+// happened to be shuffled into
+pub struct StratifiedSampler {
+    seed: u64,
+    samples_per_pixel: u32,
+    x_strata: u32,
+    y_strata: u32,
+    pixel: (i32, i32),
+    index: u32,
+    dimension: u64,
+    rng: Rng,
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, samples_per_pixel: i32) -> StratifiedSampler {
+        let samples_per_pixel = samples_per_pixel.max(1) as u32;
+        let x_strata = (samples_per_pixel as f32).sqrt() as u32;
+
+        StratifiedSampler {
+            seed,
+            samples_per_pixel,
+            x_strata,
+            y_strata: samples_per_pixel.div_ceil(x_strata),
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+            rng: seeded_rng(seed, 0),
+        }
+    }
+
+    fn permuted_index(&self, strata: u32) -> u32 {
+        let permutation = hash(&[
+            self.seed,
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.dimension,
+        ]);
+
+        permutation_element(self.index % strata, strata, permutation as u32)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, x: i32, y: i32, index: i32) {
+        self.pixel = (x, y);
+        self.index = index as u32;
+        self.dimension = 0;
+        self.rng = sample_rng(self.seed, x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let stratum = self.permuted_index(self.samples_per_pixel);
+        self.dimension += 1;
+
+        stratum_point(
+            stratum,
+            random::<f32>(&mut self.rng, 0.0, 1.0),
+            self.samples_per_pixel,
+        )
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let stratum = self.permuted_index(self.x_strata * self.y_strata);
+        self.dimension += 2;
+
+        (
+            stratum_point(
+                stratum % self.x_strata,
+                random::<f32>(&mut self.rng, 0.0, 1.0),
+                self.x_strata,
+            ),
+            stratum_point(
+                stratum / self.x_strata,
+                random::<f32>(&mut self.rng, 0.0, 1.0),
+                self.y_strata,
+            ),
+        )
+    }
+}
+
+// The point 'u' of the way into 'stratum' out of 'strata' equal ones over [0,1). Rounding can
+// push the end of the last one up to 1
+fn stratum_point(stratum: u32, u: f32, strata: u32) -> f32 {
+    f32::min((stratum as f32 + u) / strata as f32, 1.0 - f32::EPSILON)
+}
+
+// Halton sequence, every dimension is the radical inverse of the sample index in the next prime
+// base. Each pixel gets its own random toroidal shift (Cranley-Patterson rotation) per dimension
+// so neighbouring pixels don't share the same pattern. Dimensions past the prime table fall back
+// to random numbers
+pub struct HaltonSampler {
+    seed: u64,
+    primes: Vec<u32>,
+    pixel: (i32, i32),
+    index: u32,
+    dimension: usize,
+    rng: Rng,
+}
+
+impl HaltonSampler {
+    const DIMENSIONS: usize = 128;
+
+    pub fn new(seed: u64) -> HaltonSampler {
+        let mut primes = Vec::with_capacity(Self::DIMENSIONS);
+        let mut candidate = 2;
+
+        while primes.len() < Self::DIMENSIONS {
+            if primes.iter().all(|p| candidate % p != 0) {
+                primes.push(candidate);
+            }
+            candidate += 1;
+        }
+
+        HaltonSampler {
+            seed,
+            primes,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+            rng: seeded_rng(seed, 0),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, x: i32, y: i32, index: i32) {
+        self.pixel = (x, y);
+        self.index = index as u32;
+        self.dimension = 0;
+        self.rng = sample_rng(self.seed, x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        if self.dimension >= self.primes.len() {
+            return random::<f32>(&mut self.rng, 0.0, 1.0);
+        }
+
+        let shift = to_unit_float(hash(&[
+            self.seed,
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.dimension as u64,
+        ]) as u32);
+        let value = radical_inverse(self.primes[self.dimension], self.index) + shift;
+        self.dimension += 1;
+
+        // Wrap around, and stay below 1.0 even when rounding pushes it up
+        f32::min(value - value.floor(), 1.0 - f32::EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+// Scrambled Sobol. Uses the first two dimensions of the Sobol sequence, which form a (0,2)
+// sequence with excellent 2D stratification, for every request ('padding'). Each request shuffles
+// the sample order and Owen scrambles the values with its own hash, which keeps the
+// stratification while decorrelating dimensions and pixels. Works best with a power of two
+// samples per pixel
+pub struct SobolSampler {
+    seed: u64,
+    pixel: (i32, i32),
+    index: u32,
+    dimension: u64,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> SobolSampler {
+        SobolSampler {
+            seed,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn dimension_hash(&self, salt: u64) -> u32 {
+        hash(&[
+            self.seed,
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.dimension,
+            salt,
+        ]) as u32
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, x: i32, y: i32, index: i32) {
+        self.pixel = (x, y);
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let index = nested_uniform_scramble(self.index, self.dimension_hash(0));
+        let value = nested_uniform_scramble(index.reverse_bits(), self.dimension_hash(1));
+        self.dimension += 1;
+
+        to_unit_float(value)
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let index = nested_uniform_scramble(self.index, self.dimension_hash(0));
+        let x = nested_uniform_scramble(index.reverse_bits(), self.dimension_hash(1));
+        let y = nested_uniform_scramble(sobol_second_dimension(index), self.dimension_hash(2));
+        self.dimension += 2;
+
+        (to_unit_float(x), to_unit_float(y))
+    }
+}
+
+// Van der Corput sequence in the given base
+pub fn radical_inverse(base: u32, mut i: u32) -> f32 {
+    let inv_base = 1.0 / base as f32;
+    let mut inv = inv_base;
+    let mut result = 0.0;
+
+    while i > 0 {
+        result += (i % base) as f32 * inv;
+        i /= base;
+        inv *= inv_base;
+    }
+
+    result
+}
+
+// Second dimension of the Sobol sequence as 32 bits, most significant bit first
+fn sobol_second_dimension(mut i: u32) -> u32 {
+    let mut result = 0;
+    let mut v: u32 = 1 << 31;
+
+    while i != 0 {
+        if i & 1 != 0 {
+            result ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+
+    result
+}
+
+// Hash based Owen scrambling (Burley, "Practical Hash-based Owen Scrambling"). Randomly flips
+// every bit depending on the bits above it, which randomizes the points while keeping their
+// stratification
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+
+    x.reverse_bits()
+}
+
+// Element 'i' of a random permutation of [0,l) picked by 'p', without storing the permutation
+// (Kensler, "Correlated Multi-Jittered Sampling")
+fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+
+        if i < l {
+            break;
+        }
+    }
+
+    (i.wrapping_add(p)) % l
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples_in_unit_square(sampler: &mut dyn Sampler) {
+        for index in 0..64 {
+            sampler.start_sample(3, 7, index);
+
+            for _ in 0..8 {
+                let u = sampler.get_1d();
+                let (u0, u1) = sampler.get_2d();
+
+                for value in [u, u0, u1] {
+                    assert!((0.0..1.0).contains(&value), "{} is outside [0,1)", value);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn samples_are_in_unit_range() {
+        samples_in_unit_square(&mut IndependentSampler::new(1));
+        samples_in_unit_square(&mut StratifiedSampler::new(1, 64));
+        samples_in_unit_square(&mut HaltonSampler::new(1));
+        samples_in_unit_square(&mut SobolSampler::new(1));
+    }
+
+    #[test]
+    fn samples_are_random_access() {
+        let mut sampler = SobolSampler::new(5);
+        sampler.start_sample(1, 2, 9);
+        let first = (sampler.get_1d(), sampler.get_2d());
+        sampler.start_sample(4, 4, 0);
+        sampler.get_2d();
+        sampler.start_sample(1, 2, 9);
+
+        assert_eq!(first, (sampler.get_1d(), sampler.get_2d()));
+    }
+
+    // Every sample of a pixel has to land in a stratum of its own, also when samples_per_pixel
+    // isn't a perfect square
+    #[test]
+    fn stratified_samples_fill_different_strata() {
+        for samples_per_pixel in [1, 7, 16, 200] {
+            let mut sampler = StratifiedSampler::new(2, samples_per_pixel);
+            let (x_strata, y_strata) = (sampler.x_strata, sampler.y_strata);
+            let mut strata_1d = vec![false; samples_per_pixel as usize];
+            let mut strata_2d = vec![false; (x_strata * y_strata) as usize];
+
+            for index in 0..samples_per_pixel {
+                sampler.start_sample(0, 0, index);
+                let u = sampler.get_1d();
+                let (u0, u1) = sampler.get_2d();
+
+                let stratum = (u * samples_per_pixel as f32) as usize;
+                assert!(!strata_1d[stratum], "1D stratum {} used twice", stratum);
+                strata_1d[stratum] = true;
+
+                let stratum =
+                    (u1 * y_strata as f32) as u32 * x_strata + (u0 * x_strata as f32) as u32;
+                assert!(
+                    !strata_2d[stratum as usize],
+                    "2D stratum {} used twice",
+                    stratum
+                );
+                strata_2d[stratum as usize] = true;
+            }
+        }
+    }
+
+    #[test]
+    fn stratum_points_stay_below_1() {
+        let u = 1.0 - f32::EPSILON / 2.0;
+        assert_eq!(199.0 + u, 200.0);
+
+        for strata in [200, 999, 1000, 4095] {
+            assert!(stratum_point(strata - 1, u, strata) < 1.0);
+        }
+
+        let mut sampler = StratifiedSampler::new(3, 999);
+        for index in 0..999 {
+            sampler.start_sample(0, 0, index);
+            let (u0, u1) = sampler.get_2d();
+
+            for value in [sampler.get_1d(), u0, u1] {
+                assert!((0.0..1.0).contains(&value), "{} is outside [0,1)", value);
+            }
+        }
+    }
+
+    #[test]
+    fn radical_inverse_base_2() {
+        assert_eq!(radical_inverse(2, 0), 0.0);
+        assert_eq!(radical_inverse(2, 1), 0.5);
+        assert_eq!(radical_inverse(2, 2), 0.25);
+        assert_eq!(radical_inverse(2, 3), 0.75);
+    }
+}
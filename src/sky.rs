@@ -0,0 +1,183 @@
+use crate::color::{transform, ColorSpace};
+use crate::light::SunLight;
+use crate::rtweekend::degrees_to_radians;
+use crate::{Color, Vec3};
+
+// Clear sky after Preetham, Shirley and Smits, "A Practical Analytic Model for Daylight". The sun
+// is 'elevation' degrees above the horizon and 'azimuth' degrees around from -Z towards +X (so
+// north is -Z and east is +X). Turbidity is how hazy the air is: 2 is a very clear day, 3 a
+// usual one and 10 a hazy one.
+//
+// Values are in cd/m^2 like the physical camera expects, 'intensity' scales them for the other
+// cameras. Below the horizon there's ground that reflects 'ground_albedo' of the sky
+pub struct PhysicalSky {
+    sun_direction: Vec3,
+    sun_zenith: f32,
+    turbidity: f32,
+    intensity: f32,
+    ground_albedo: f32,
+    working_space: ColorSpace,
+    // Perez coefficients A to E of the luminance and the two chromaticities
+    perez: [[f32; 5]; 3],
+    // Luminance and chromaticity straight up
+    zenith: [f32; 3],
+}
+
+impl PhysicalSky {
+    // Extraterrestrial luminance of the sun, before the atmosphere dims it
+    const SUN_LUMINANCE: f32 = 1.6e9;
+
+    pub fn new(
+        elevation: f32,
+        azimuth: f32,
+        turbidity: Option<f32>,
+        intensity: Option<f32>,
+        ground_albedo: Option<f32>,
+        working_space: ColorSpace,
+    ) -> PhysicalSky {
+        let turbidity = turbidity.unwrap_or(3.0).clamp(1.7, 10.0);
+        let elevation = degrees_to_radians(elevation);
+        let azimuth = degrees_to_radians(azimuth);
+        let sun_direction = Vec3::new(
+            Some(azimuth.sin() * elevation.cos()),
+            Some(elevation.sin()),
+            Some(-azimuth.cos() * elevation.cos()),
+        );
+        // The model doesn't go below the horizon
+        let sun_zenith = (std::f32::consts::FRAC_PI_2 - elevation).clamp(0.0, 1.57);
+        let t = turbidity;
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+        let theta = sun_zenith;
+        let theta2 = theta * theta;
+        let theta3 = theta2 * theta;
+        let chi = (4.0 / 9.0 - t / 120.0) * (std::f32::consts::PI - 2.0 * theta);
+        // In kcd/m^2
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let zenith_x = t * t * (0.00166 * theta3 - 0.00375 * theta2 + 0.00209 * theta)
+            + t * (-0.02903 * theta3 + 0.06377 * theta2 - 0.03202 * theta + 0.00394)
+            + (0.11693 * theta3 - 0.21196 * theta2 + 0.06052 * theta + 0.25886);
+        let zenith_y = t * t * (0.00275 * theta3 - 0.00610 * theta2 + 0.00317 * theta)
+            + t * (-0.04214 * theta3 + 0.08970 * theta2 - 0.04153 * theta + 0.00516)
+            + (0.15346 * theta3 - 0.26756 * theta2 + 0.06670 * theta + 0.26688);
+
+        PhysicalSky {
+            sun_direction,
+            sun_zenith,
+            turbidity,
+            intensity: intensity.unwrap_or(1.0),
+            ground_albedo: ground_albedo.unwrap_or(0.3),
+            working_space,
+            perez,
+            zenith: [zenith_luminance.max(0.0) * 1000.0, zenith_x, zenith_y],
+        }
+    }
+
+    // Radiance of the sky (without the sun itself) towards 'direction'
+    pub fn radiance(&self, direction: &Vec3) -> Color {
+        let mut direction = Vec3::unit_vector(*direction);
+        let mut scale = self.intensity;
+
+        // Ground, lit by the sky it mirrors
+        if direction.y() < 0.0 {
+            direction = Vec3::new(
+                Some(direction.x()),
+                Some(-direction.y()),
+                Some(direction.z()),
+            );
+            scale *= self.ground_albedo;
+        }
+
+        // Just above the horizon, where the model blows up
+        let cos_theta = direction.y().max(0.01);
+        let cos_gamma = direction.dot(&self.sun_direction).clamp(-1.0, 1.0);
+        let gamma = cos_gamma.acos();
+        let mut yxy = [0.0; 3];
+
+        for (value, (perez, zenith)) in yxy.iter_mut().zip(self.perez.iter().zip(&self.zenith)) {
+            *value = zenith * perez_function(perez, cos_theta, gamma, cos_gamma)
+                / perez_function(perez, 1.0, self.sun_zenith, self.sun_zenith.cos());
+        }
+
+        let [luminance, x, y] = yxy;
+        let xyz = Color::new(
+            Some(x / y * luminance),
+            Some(luminance),
+            Some((1.0 - x - y) / y * luminance),
+        );
+
+        self.working_space
+            .convert_linear_srgb(xyz_to_linear_srgb(xyz) * scale)
+    }
+
+    // The sun as a light, with the color the atmosphere leaves it. Its disk is 'angular_diameter'
+    // degrees wide, 0.53 by default. None when it's below the horizon
+    pub fn sun(&self, angular_diameter: Option<f32>) -> Option<SunLight> {
+        if self.sun_direction.y() <= 0.0 {
+            return None;
+        }
+
+        // Air mass the light goes through (Kasten and Young), then Rayleigh scattering and haze
+        // (Angstrom's formula) at a red, green and blue wavelength in micrometers
+        let zenith_degrees = self.sun_zenith.to_degrees();
+        let air_mass =
+            1.0 / (self.sun_zenith.cos() + 0.50572 * (96.07995 - zenith_degrees).powf(-1.6364));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let mut transmittance = Color::new(None, None, None);
+
+        for (value, wavelength) in transmittance.e.iter_mut().zip([0.68f32, 0.55, 0.44]) {
+            let rayleigh = 0.008735 * wavelength.powf(-4.08);
+            let haze = beta * wavelength.powf(-1.3);
+            *value = (-air_mass * (rayleigh + haze)).exp();
+        }
+
+        let radiance = self
+            .working_space
+            .convert_linear_srgb(transmittance * (Self::SUN_LUMINANCE * self.intensity));
+
+        Some(SunLight::new(
+            self.sun_direction,
+            radiance,
+            angular_diameter,
+        ))
+    }
+}
+
+// Perez et al.'s sky distribution, 'theta' being the angle from the zenith and 'gamma' the one
+// from the sun
+fn perez_function(coefficients: &[f32; 5], cos_theta: f32, gamma: f32, cos_gamma: f32) -> f32 {
+    let [a, b, c, d, e] = *coefficients;
+
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+fn xyz_to_linear_srgb(xyz: Color) -> Color {
+    const XYZ_TO_SRGB: [[f32; 3]; 3] = [
+        [3.240454, -1.537139, -0.498531],
+        [-0.969266, 1.876011, 0.041556],
+        [0.055643, -0.204026, 1.057225],
+    ];
+
+    transform(&XYZ_TO_SRGB, xyz)
+}
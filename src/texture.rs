@@ -1,4 +1,6 @@
+use crate::color::{srgb_to_linear, ColorSpace};
 use crate::perlin::Perlin;
+use crate::rtweekend::Rng;
 use crate::{clamp, Color, Point3};
 use stb_image::image::LoadResult;
 use std::rc::Rc;
@@ -65,9 +67,9 @@ pub struct NoiseTexture {
 }
 
 impl NoiseTexture {
-    pub fn new(scale: Option<f32>) -> NoiseTexture {
+    pub fn new(rng: &mut Rng, scale: Option<f32>) -> NoiseTexture {
         NoiseTexture {
-            noise: Perlin::new(),
+            noise: Perlin::new(rng),
             scale: scale.unwrap_or(1.0),
         }
     }
@@ -81,38 +83,58 @@ impl Texture for NoiseTexture {
     }
 }
 
+// 8 bit images store sRGB encoded values and HDR (float) images linear ones. Either way they're
+// decoded to linear light and converted to the working space when loaded
 pub struct ImageTexture {
-    data: Option<Vec<u8>>,
+    data: Option<Vec<Color>>,
     width: i32,
     height: i32,
-    bytes_per_scanline: i32,
 }
 
 impl ImageTexture {
-    const BYTES_PER_PIXEL: i32 = 3;
-
     pub fn empty() -> ImageTexture {
         ImageTexture {
             data: None,
             width: 0,
             height: 0,
-            bytes_per_scanline: 0,
         }
     }
 
-    pub fn new(filename: &str) -> ImageTexture {
-        let img_res = stb_image::image::load(filename);
-        let temp_data = match img_res {
-            LoadResult::Error(_) => return Self::empty(),
-            LoadResult::ImageU8(image) => image,
-            LoadResult::ImageF32(_) => return Self::empty(),
-        };
+    pub fn new(filename: &str, working_space: ColorSpace) -> ImageTexture {
+        let (texels, width, height): (Vec<Color>, usize, usize) =
+            match stb_image::image::load(filename) {
+                LoadResult::Error(_) => return Self::empty(),
+                LoadResult::ImageU8(image) => {
+                    // Only 256 possible values, decode them once
+                    let decode: Vec<f32> = (0..256)
+                        .map(|value| srgb_to_linear(value as f32 / 255.0))
+                        .collect();
+                    let texels = image
+                        .data
+                        .chunks_exact(image.depth)
+                        .map(|texel| texel_color(texel, &|value| decode[value as usize]))
+                        .collect();
+                    (texels, image.width, image.height)
+                }
+                LoadResult::ImageF32(image) => {
+                    let texels = image
+                        .data
+                        .chunks_exact(image.depth)
+                        .map(|texel| texel_color(texel, &|value| value))
+                        .collect();
+                    (texels, image.width, image.height)
+                }
+            };
 
         ImageTexture {
-            data: Some(temp_data.data),
-            width: temp_data.width as i32,
-            height: temp_data.height as i32,
-            bytes_per_scanline: Self::BYTES_PER_PIXEL * temp_data.width as i32,
+            data: Some(
+                texels
+                    .into_iter()
+                    .map(|color| working_space.convert_linear_srgb(color))
+                    .collect(),
+            ),
+            width: width as i32,
+            height: height as i32,
         }
     }
 }
@@ -135,13 +157,44 @@ impl Texture for ImageTexture {
         if j >= self.height {
             j = self.height - 1;
         }
-        let color_scale: f32 = 1.0 / 255.0;
-        let pixel = (j * self.bytes_per_scanline + i * Self::BYTES_PER_PIXEL) as usize;
-
-        Color::new(
-            Some(self.data.as_ref().unwrap()[pixel] as f32 * color_scale),
-            Some(self.data.as_ref().unwrap()[pixel + 1] as f32 * color_scale),
-            Some(self.data.as_ref().unwrap()[pixel + 2] as f32 * color_scale),
-        )
+
+        self.data.as_ref().unwrap()[(j * self.width + i) as usize]
+    }
+}
+
+// First three channels of a texel, gray images get the same value in all three
+pub fn texel_color<T: Copy>(texel: &[T], decode: &dyn Fn(T) -> f32) -> Color {
+    if texel.len() < 3 {
+        let value = decode(texel[0]);
+        return Color::new(Some(value), Some(value), Some(value));
+    }
+
+    Color::new(
+        Some(decode(texel[0])),
+        Some(decode(texel[1])),
+        Some(decode(texel[2])),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hdr_images_load_as_linear_values() {
+        let filename =
+            std::env::temp_dir().join(format!("texture_test_{}.hdr", std::process::id()));
+        let filename = filename.to_str().unwrap();
+        // Two RGBE pixels, (1, 0.5, 0.25) and (4, 2, 0)
+        let mut file = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n".to_vec();
+        file.extend_from_slice(&[128, 64, 32, 129, 128, 64, 0, 131]);
+        std::fs::write(filename, &file).unwrap();
+
+        let texture = ImageTexture::new(filename, ColorSpace::LinearSrgb);
+        std::fs::remove_file(filename).unwrap();
+        let origin = Point3::new(None, None, None);
+
+        assert_eq!(texture.value(0.25, 0.5, &origin).e, [1.0, 0.5, 0.25]);
+        assert_eq!(texture.value(0.75, 0.5, &origin).e, [4.0, 2.0, 0.0]);
     }
 }
@@ -0,0 +1,120 @@
+use crate::color::transform;
+use crate::Color;
+
+// Squeezes scene radiance, which can be anything from 0 up, into the 0 to 1 range a display can
+// show. Works in linear sRGB and the output is still linear, the transfer function comes after
+#[derive(Clone, Copy)]
+pub enum ToneMapper {
+    // Everything above 1 is cut off, bright lights turn into flat white
+    Clamp,
+    // L / (1 + L) on the luminance. Never reaches white
+    Reinhard,
+    // Reinhard that reaches white at the given luminance
+    ExtendedReinhard(f32),
+    // Stephen Hill's fit of the ACES reference rendering and sRGB output transforms
+    Aces,
+    // Minimal AgX (Troy Sobotka's look, Benjamin Wrensch's fit). Desaturates very bright colors
+    // towards white the way film does instead of skewing their hue
+    Agx,
+}
+
+impl ToneMapper {
+    pub fn apply(&self, color: Color) -> Color {
+        match self {
+            ToneMapper::Clamp => clamp(color),
+            ToneMapper::Reinhard => {
+                let luminance = color.luminance();
+                scale_luminance(color, luminance / (1.0 + luminance))
+            }
+            ToneMapper::ExtendedReinhard(white) => {
+                let luminance = color.luminance();
+                let mapped = luminance * (1.0 + luminance / (white * white)) / (1.0 + luminance);
+                clamp(scale_luminance(color, mapped))
+            }
+            ToneMapper::Aces => aces(color),
+            ToneMapper::Agx => agx(color),
+        }
+    }
+}
+
+fn clamp(color: Color) -> Color {
+    Color::new(
+        Some(color.x().clamp(0.0, 1.0)),
+        Some(color.y().clamp(0.0, 1.0)),
+        Some(color.z().clamp(0.0, 1.0)),
+    )
+}
+
+// Scales the color so its luminance becomes 'luminance', keeping the hue
+fn scale_luminance(color: Color, luminance: f32) -> Color {
+    let current = color.luminance();
+
+    if current <= 0.0 {
+        return Color::new(None, None, None);
+    }
+
+    color * (luminance / current)
+}
+
+fn aces(color: Color) -> Color {
+    // sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT
+    const INPUT: [[f32; 3]; 3] = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    // ODT_SAT => XYZ => D60_2_D65 => sRGB
+    const OUTPUT: [[f32; 3]; 3] = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+
+    let mut v = transform(&INPUT, color);
+
+    for value in v.e.iter_mut() {
+        let a = *value * (*value + 0.0245786) - 0.000090537;
+        let b = *value * (0.983729 * *value + 0.432951) + 0.238081;
+        *value = a / b;
+    }
+
+    clamp(transform(&OUTPUT, v))
+}
+
+fn agx(color: Color) -> Color {
+    const INSET: [[f32; 3]; 3] = [
+        [0.84247905, 0.0784336, 0.079223745],
+        [0.042328242, 0.87846863, 0.07916613],
+        [0.042375654, 0.0784336, 0.879143],
+    ];
+    const OUTSET: [[f32; 3]; 3] = [
+        [1.196879, -0.09802088, -0.09902974],
+        [-0.052896854, 1.1519032, -0.098961174],
+        [-0.052971635, -0.09804345, 1.1510737],
+    ];
+    // Range of the log encoding, in stops around middle grey
+    const MIN_EV: f32 = -12.47393;
+    const MAX_EV: f32 = 4.026069;
+
+    let mut v = transform(&INSET, color);
+
+    for value in v.e.iter_mut() {
+        let x = (value.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        // Polynomial fit of the AgX sigmoid
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        *value = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x
+            + 0.4298 * x2
+            + 0.1191 * x
+            - 0.00232;
+    }
+
+    let v = transform(&OUTSET, v);
+
+    // The curve gives display values for a 2.2 gamma display, back to linear
+    clamp(Color::new(
+        Some(v.x().max(0.0).powf(2.2)),
+        Some(v.y().max(0.0).powf(2.2)),
+        Some(v.z().max(0.0).powf(2.2)),
+    ))
+}
@@ -35,6 +35,11 @@ impl Vec3 {
         self.len_squared().sqrt()
     }
 
+    // Perceived brightness of a linear Rec.709/sRGB color
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.e[0] + 0.7152 * self.e[1] + 0.0722 * self.e[2]
+    }
+
     // A Vec3 calculates the dot product of itself and another vector.  The 'v' parameter simply
     // specifies the 2nd vector
     pub fn dot(&self, v: &Vec3) -> f32 {
@@ -59,17 +64,17 @@ impl Vec3 {
     // Basically another type of constructor only that this time, it generates a Vec3 with random
     // X, Y and Z values within the specified range on the parameters, or the defaults. See the
     // random_double() function
-    pub fn random(min: Option<f32>, max: Option<f32>) -> Vec3 {
+    pub fn random(rng: &mut Rng, min: Option<f32>, max: Option<f32>) -> Vec3 {
         Vec3::new(
-            Some(random::<f32>(min.unwrap_or(0.0), max.unwrap_or(1.0))),
-            Some(random::<f32>(min.unwrap_or(0.0), max.unwrap_or(1.0))),
-            Some(random::<f32>(min.unwrap_or(0.0), max.unwrap_or(1.0))),
+            Some(random::<f32>(rng, min.unwrap_or(0.0), max.unwrap_or(1.0))),
+            Some(random::<f32>(rng, min.unwrap_or(0.0), max.unwrap_or(1.0))),
+            Some(random::<f32>(rng, min.unwrap_or(0.0), max.unwrap_or(1.0))),
         )
     }
 
-    pub fn random_in_unit_sphere() -> Vec3 {
+    pub fn random_in_unit_sphere(rng: &mut Rng) -> Vec3 {
         loop {
-            let p = Vec3::random(Some(-1.0), Some(1.0));
+            let p = Vec3::random(rng, Some(-1.0), Some(1.0));
 
             if p.len_squared() >= 1.0 {
                 continue;
@@ -79,12 +84,8 @@ impl Vec3 {
         }
     }
 
-    pub fn random_unit_vector() -> Vec3 {
-        Vec3::unit_vector(Vec3::random_in_unit_sphere())
-    }
-
-    pub fn random_in_hemisphere(normal: &Vec3) -> Vec3 {
-        let in_unit_sphere = Vec3::random_in_unit_sphere();
+    pub fn random_in_hemisphere(rng: &mut Rng, normal: &Vec3) -> Vec3 {
+        let in_unit_sphere = Vec3::random_in_unit_sphere(rng);
 
         return if in_unit_sphere.dot(normal) > 0.0 {
             in_unit_sphere
@@ -111,20 +112,41 @@ impl Vec3 {
         r_out_perp + r_out_parallel
     }
 
-    pub fn random_in_unit_disk() -> Vec3 {
-        loop {
-            let p = Vec3::new(
-                Some(random::<f32>(-1.0, 1.0)),
-                Some(random::<f32>(-1.0, 1.0)),
-                None,
-            );
+    // The sample_* functions below warp uniform numbers in [0,1) coming from a Sampler into
+    // other distributions. Unlike the rejection loops above they always use the same amount of
+    // numbers, which is what low discrepancy samplers need
 
-            if p.len_squared() >= 1.0 {
-                continue;
-            }
+    // Uniformly distributed direction
+    pub fn sample_unit_vector(u: (f32, f32)) -> Vec3 {
+        let z = 1.0 - 2.0 * u.0;
+        let r = f32::max(0.0, 1.0 - z * z).sqrt();
+        let phi = 2.0 * PI * u.1;
 
-            return p;
+        Vec3::new(Some(r * phi.cos()), Some(r * phi.sin()), Some(z))
+    }
+
+    // Uniformly distributed point inside the unit sphere, the third number picks the radius
+    pub fn sample_in_unit_sphere(u: (f32, f32), r: f32) -> Vec3 {
+        Vec3::sample_unit_vector(u) * r.cbrt()
+    }
+
+    // Uniformly distributed point inside the unit disk on the z = 0 plane. Uses the concentric
+    // mapping, which keeps neighbouring samples close together
+    pub fn sample_in_unit_disk(u: (f32, f32)) -> Vec3 {
+        let x = 2.0 * u.0 - 1.0;
+        let y = 2.0 * u.1 - 1.0;
+
+        if x == 0.0 && y == 0.0 {
+            return Vec3::new(None, None, None);
         }
+
+        let (r, theta) = if x.abs() > y.abs() {
+            (x, PI / 4.0 * (y / x))
+        } else {
+            (y, PI / 2.0 - PI / 4.0 * (x / y))
+        };
+
+        Vec3::new(Some(r * theta.cos()), Some(r * theta.sin()), None)
     }
 }
 
@@ -162,7 +184,7 @@ impl std::ops::DivAssign<f32> for Vec3 {
     }
 }
 
-use crate::rtweekend::random;
+use crate::rtweekend::{random, Rng, PI};
 pub use Vec3 as Point3;
 pub use Vec3 as Color;
 